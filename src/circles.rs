@@ -1,15 +1,20 @@
 //! # A circle drawing application
 //! This will be revisited at a later date as there's likely a cleaner solution.
-//! The slider from the second window and the circle being drawn is not smooth.
+//! Besides circles it draws ellipses, rectangles, lines and polygons, picked from the toolbar.
+//! The size is adjusted from an overlay drawn inside the canvas, next to the selected shape.
 
 use druid::{AppLauncher, WindowDesc, Widget, PlatformError, Data, Lens, Size, WidgetExt, Color,
-            Selector, MenuDesc, MenuItem, LocalizedString, DelegateCtx, Target, Command, KeyCode};
+            Selector, MenuDesc, MenuItem, LocalizedString, DelegateCtx, Target, Command, KeyCode, WindowId,
+            Application, Clipboard, ClipboardFormat, FormatId, FileSpec};
 use druid::widget::prelude::*;
-use druid::widget::{Flex, Button, MainAxisAlignment, Slider, Label, Controller};
-use druid::{ContextMenu, AppDelegate, WindowId, Key};
+use druid::widget::{Flex, Button, MainAxisAlignment, Slider, Label, Checkbox};
+use druid::{ContextMenu, AppDelegate, Key, LensExt};
 use druid::im::Vector;
 
-use crate::circles::custom::{CanvasData};
+use crate::circles::custom::{CanvasData, Placement, Arrange};
+use crate::circles::shapes::{Tool, StyleChange};
+use crate::persist::{self, Persist};
+use crate::menus::{self, MenuState, TaskMenu, ViewItem};
 
 /*
 TODO:
@@ -19,13 +24,23 @@ TODO:
     [X] add a slider to control radius of currently selected
     [X] add a context menu
     [X] add the slider to a context menu
-    [ ] change slider to only affect the circle selected at time window is open, disable enable canvas
+    [X] change slider to only affect the circle selected at time window is open, disable enable canvas
     [X] check that only one pop-up can occur at one time and close on lost focus
-    [ ] add a list of instructions to implement undo and redo functionality
+    [X] add a list of instructions to implement undo and redo functionality
     [ ] temp functionality: redo adds an circle to the middle, undo removes it and prints current status
-    [*] Lag issue between multiple windows
+    [X] Lag issue between multiple windows, the popup window is now an overlay in the canvas
     [X] add escape key to set selection to None
-    [ ] add scroll functionality
+    [X] drag to move a circle, drag the handle on its rim to resize
+    [X] index circles in a grid so clicks and painting don't look at every circle
+    [X] add scroll functionality, the canvas pans, zooms and has scrollbars
+    [X] more shapes than circles, each written to and read from a line of text
+    [X] stroke, fill and opacity for each shape, bring to front and send to back
+    [X] select several shapes with a rubber band or shift-click, move, resize, delete and duplicate them together
+    [X] delete with the keyboard, cut, copy and paste through the clipboard with an SVG copy for other apps
+    [X] keyboard only: Tab selects, arrows nudge, +/- resize, Enter opens the overlay, Space draws at a cursor
+    [X] a grid to snap to, guides to line shapes up with each other, align and distribute
+    [X] a menu bar, the drawing can be started again, opened and saved from the File menu
+    [X] the drawing is kept between runs along with the state of the other tasks
  */
 
 /**
@@ -34,30 +49,38 @@ Tried to fix Lag issue via commands and target specific window/widget instead of
 The lag issue could not be solved, merely linking two windows via one lens lags out the
 other window (works both ways).
 Discussed on Zulip it is not occuring on MacOS, could be a windows issue
+Resolved by replacing the popup window with an overlay in the canvas, there is only one window
  **/
 
 const WINDOW_TITLE: &str = "Circles";
-const WINDOW_SIZE: Size = Size::new(500., 500.);
-const WINDOW_SIZE_MIN: Size = Size::new(250., 250.);
+const WINDOW_SIZE: Size = Size::new(560., 500.);
+const WINDOW_SIZE_MIN: Size = Size::new(520., 300.);
 const PADDING: f64 = 8.;
-const POPUP_SIZE: Size = Size::new(250., 100.);
+const OVERLAY_WIDTH: f64 = 220.;
+/// Shapes on the clipboard in the format of the save file, so they keep everything between drawings
+const SHAPES_FORMAT: FormatId = "application/x-druid-7guis-shapes";
+/// Drawings opened and saved from the File menu, in the format of the save file
+const DRAWING_FILE: FileSpec = FileSpec::new("Drawing", &["tsv"]);
 
 const BTN_CLR_DISABLED: Key<Color> = Key::new("app.btn.clr.disabled");
 const BTN_TXT_DISABLED: Key<Color> = Key::new("app.btn.txt.disabled");
+const BTN_CLR_ACTIVE: Key<Color> = Key::new("app.btn.clr.active");
 
 const MAX_RADIUS: f64 = 100.;
 const MIN_RADIUS: f64 = 5.;
 
 pub fn main()-> Result<(), PlatformError>  {
-    let data = AppData::new();
+    let data = persist::restore().unwrap_or_else(AppData::new);
     let window = WindowDesc::new(build_ui)
         .window_size(WINDOW_SIZE)
         .with_min_size(WINDOW_SIZE_MIN)
-        .title(WINDOW_TITLE);
+        .title(WINDOW_TITLE)
+        .menu(menus::menu_bar(&data.menu_state()));
     AppLauncher::with_window(window)
         .configure_env(|env, _state| {
             env.set(BTN_TXT_DISABLED, Color::grey(0.7));
             env.set(BTN_CLR_DISABLED, Color::grey(0.5));
+            env.set(BTN_CLR_ACTIVE, Color::rgb8(0x3c, 0x6e, 0xa8));
         })
         .delegate(Delegate)
         .launch(data)?;
@@ -67,10 +90,8 @@ pub fn main()-> Result<(), PlatformError>  {
 #[derive(Clone, Data, Lens)]
 struct AppData{
     canvas: custom::CanvasData,
-    radius: f64,
     undo_valid: bool,
     redo_valid: bool,
-    window_count: u32,
     action_log: ActionLog,
 }
 
@@ -78,13 +99,79 @@ impl AppData {
     fn new() -> Self {
         AppData {
             canvas: CanvasData::new(),
-            radius: (MAX_RADIUS + MIN_RADIUS) / 2.,
             undo_valid: false,
             redo_valid: false,
-            window_count: 0,
             action_log: ActionLog::default(),
         }
     }
+
+    /// Closes the resize overlay, logging any change made with it
+    fn finish_resize(&mut self) {
+        if let Some(item) = self.canvas.close_resize() {
+            self.action_log.record(item);
+        }
+    }
+
+    /// Runs a change to the drawing and logs it, if anything changed
+    fn apply(&mut self, f: impl FnOnce(&mut CanvasData) -> Option<ActionItem>) {
+        self.finish_resize();
+        if let Some(item) = f(&mut self.canvas) {
+            self.action_log.record(item);
+        }
+        self.update_history();
+    }
+
+    /// Enables the undo and redo buttons to match the action log
+    fn update_history(&mut self) {
+        self.undo_valid = self.action_log.can_undo();
+        self.redo_valid = self.action_log.can_redo();
+    }
+}
+
+/// Only the drawing is kept between runs, the history starts empty.
+/// The format hasn't changed since before there were versions
+impl Persist for AppData {
+    const FILE: &'static str = "circles.tsv";
+    const VERSION: u32 = 1;
+
+    fn to_save(&self) -> String {
+        self.canvas.to_tsv()
+    }
+
+    fn from_save(text: &str, _version: u32) -> Option<Self> {
+        let canvas = CanvasData::from_tsv(text);
+        // unreadable lines are skipped, a file of nothing but those isn't a drawing
+        if canvas.content_bounds().is_none() && !text.trim().is_empty() {
+            return None;
+        }
+        Some(AppData { canvas, ..AppData::new() })
+    }
+}
+
+/// New clears the drawing and Open replaces it, both can be undone
+impl TaskMenu for AppData {
+    fn menu_state(&self) -> MenuState {
+        let selected = !self.canvas.selected.is_empty();
+        let placement = self.canvas.placement;
+        let drawn = self.canvas.content_bounds().is_some();
+        MenuState {
+            new: drawn,
+            open: true,
+            save: true,
+            file: Some(DRAWING_FILE),
+            undo: self.undo_valid,
+            redo: self.redo_valid,
+            cut: selected,
+            copy: selected,
+            paste: true,
+            view: vec![
+                ViewItem::new("Fit All", CVS_FIT_ALL).key("0").enabled(drawn),
+                ViewItem::new("Show Grid", CVS_VIEW_GRID).key("g").checked(placement.show_grid).separated(),
+                ViewItem::new("Snap to Grid", CVS_VIEW_SNAP).checked(placement.snap),
+                ViewItem::new("Guides", CVS_VIEW_GUIDES).checked(placement.guides),
+            ],
+        }
+    }
 }
 
 fn build_ui() -> impl Widget<AppData> {
@@ -124,72 +211,180 @@ fn build_ui() -> impl Widget<AppData> {
             ctx.submit_command(druid::commands::REDO, Target::Global);
             });
 
+    // handled by the canvas, which knows its own size
+    let btn_fit = Button::new("Fit All")
+        .on_click(|ctx, _data: &mut AppData, _env| {
+            ctx.submit_command(CVS_FIT_ALL, None);
+            });
+
+    // the placement is part of the canvas data, which has no lenses of its own
+    let placement = Flex::row()
+        .with_child(Checkbox::new("Grid").lens(Placement::show_grid))
+        .with_spacer(PADDING)
+        .with_child(Checkbox::new("Snap").lens(Placement::snap))
+        .with_spacer(PADDING)
+        .with_child(Checkbox::new("Guides").lens(Placement::guides))
+        .lens(druid::lens::Field::new(
+            |data: &AppData| &data.canvas.placement,
+            |data: &mut AppData| &mut data.canvas.placement,
+        ));
+
     let header = Flex::row()
         .main_axis_alignment(MainAxisAlignment::Center)
         .with_child(btn_undo)
         .with_spacer(PADDING * 2.)
-        .with_child(btn_redo);
+        .with_child(btn_redo)
+        .with_spacer(PADDING * 2.)
+        .with_child(btn_fit)
+        .with_spacer(PADDING * 2.)
+        .with_child(placement);
+
+    let mut toolbar = Flex::row()
+        .main_axis_alignment(MainAxisAlignment::Center);
+    for (i, &tool) in Tool::ALL.iter().enumerate() {
+        if i > 0 {
+            toolbar.add_spacer(PADDING);
+        }
+        toolbar.add_child(build_tool_button(tool));
+    }
 
-    let canvas = custom::Canvas.lens(AppData::canvas);
+    let canvas = custom::Canvas::new().lens(AppData::canvas);
 
     Flex::column()
         .with_child(header)
+        .with_spacer(PADDING)
+        .with_child(toolbar)
         .with_spacer(PADDING * 2.)
         .with_flex_child(canvas, 1.)
         .with_spacer(PADDING * 2.)
         .padding(PADDING * 2.)
+        .controller(menus::MenuBar)
+}
+
+/// Picks the shape drawn on the canvas, the button of the current tool is highlighted
+fn build_tool_button(tool: Tool) -> impl Widget<AppData> {
+    Button::new(tool.name())
+        .env_scope(move |env, data: &AppData| {
+            if data.canvas.tool == tool {
+                env.set(druid::theme::BUTTON_DARK, env.get(BTN_CLR_ACTIVE));
+                env.set(druid::theme::BUTTON_LIGHT, env.get(BTN_CLR_ACTIVE));
+            }
+        })
+        .on_click(move |_ctx, data: &mut AppData, _env| {
+            data.canvas.tool = tool;
+        })
 }
 
-fn build_popup() -> impl Widget<AppData> {
+/// The size overlay, shown in the canvas next to the selected shape.
+/// The slider writes straight to the shape, the change is logged when the overlay closes.
+fn build_overlay() -> impl Widget<CanvasData> {
 
-    let lbl = Label::new(|data: &AppData, _: &_| {
-        if let Some(i) = data.canvas.selected {
-            format!("Radius for Circle {} = {:.1}", i , data.radius)
-        } else {
-            "Nothing Selected".to_string()
+    let lbl = Label::new(|data: &CanvasData, _: &_| {
+        match data.selection_name() {
+            Some(name) => format!("Size of {} = {:.1}", name, custom::SelectedSize.get(data)),
+            None => "Nothing Selected".to_string(),
         }
     });
 
     let slider = Slider::new()
         .with_range(MIN_RADIUS, MAX_RADIUS)
         .expand_width()
-        .lens(AppData::radius)
-        .controller(RadController);
+        .lens(custom::SelectedSize);
+
+    let btn_done = Button::new("Done")
+        .on_click(|ctx, data: &mut CanvasData, _env| {
+            if let Some(item) = data.close_resize() {
+                ctx.submit_command(RECORD_ACTION.with(item), None);
+            }
+        });
 
     Flex::column()
-        .main_axis_alignment(MainAxisAlignment::Center)
-        .with_flex_child(lbl, 1.)
-        .with_flex_child(slider, 1.)
+        .with_child(lbl)
+        .with_spacer(PADDING)
+        .with_child(slider)
+        .with_spacer(PADDING)
+        .with_child(btn_done)
         .padding(PADDING * 2.)
-}
-
-/// This controller is used to update the radius of the current circle
-struct RadController;
-
-impl <W: Widget<AppData>> Controller<AppData, W> for RadController {
-    fn event(
-        &mut self,
-        child: &mut W,
-        ctx: &mut EventCtx,
-        event: &Event,
-        data: &mut AppData,
-        env: &Env,
-    ) {
-        child.event(ctx, event, data, env);
-
-        if let Event::MouseMove(_) = event {
-            data.canvas.update_radius(data.radius);
-        }
-    }
+        .fix_width(OVERLAY_WIDTH)
+        .background(druid::theme::BACKGROUND_LIGHT)
+        .border(Color::BLACK, 1.)
 }
 
 /// ## Context Menu
 
 const CVS_CTX_RESIZE: Selector = Selector::new("ctx-menu-resize");
 const CVS_CTX_DESELECT: Selector = Selector::new("ctx-menu-deselect");
+const CVS_CTX_STYLE: Selector<StyleChange> = Selector::new("ctx-menu-style");
+const CVS_CTX_FRONT: Selector = Selector::new("ctx-menu-bring-to-front");
+const CVS_CTX_BACK: Selector = Selector::new("ctx-menu-send-to-back");
+const CVS_CTX_DUPLICATE: Selector = Selector::new("ctx-menu-duplicate");
+const CVS_CTX_DELETE: Selector = Selector::new("ctx-menu-delete");
+const CVS_CTX_ARRANGE: Selector<Arrange> = Selector::new("ctx-menu-arrange");
+const CVS_FIT_ALL: Selector = Selector::new("app.circles.fit-all");
+const CVS_VIEW_GRID: Selector = Selector::new("app.circles.view-grid");
+const CVS_VIEW_SNAP: Selector = Selector::new("app.circles.view-snap");
+const CVS_VIEW_GUIDES: Selector = Selector::new("app.circles.view-guides");
+
+/// The colours offered for strokes and fills
+const PALETTE: [(&str, Color); 9] = [
+    ("Black", Color::BLACK),
+    ("White", Color::WHITE),
+    ("Grey", Color::grey8(0x80)),
+    ("Red", Color::rgb8(0xd0, 0x20, 0x20)),
+    ("Orange", Color::rgb8(0xf0, 0x8c, 0x00)),
+    ("Yellow", Color::rgb8(0xf0, 0xd0, 0x20)),
+    ("Green", Color::rgb8(0x30, 0xa0, 0x40)),
+    ("Blue", Color::rgb8(0x20, 0x60, 0xd0)),
+    ("Purple", Color::rgb8(0x80, 0x30, 0xa0)),
+];
+const STROKE_WIDTHS: [(&str, f64); 4] = [("1 px", 1.), ("2 px", 2.), ("4 px", 4.), ("8 px", 8.)];
+const OPACITIES: [(&str, f64); 4] = [("100%", 1.), ("75%", 0.75), ("50%", 0.5), ("25%", 0.25)];
+const ARRANGEMENTS: [(&str, Arrange); 8] = [
+    ("Align Left", Arrange::Left),
+    ("Align Centre", Arrange::Centre),
+    ("Align Right", Arrange::Right),
+    ("Align Top", Arrange::Top),
+    ("Align Middle", Arrange::Middle),
+    ("Align Bottom", Arrange::Bottom),
+    ("Distribute Across", Arrange::Across),
+    ("Distribute Down", Arrange::Down),
+];
+
+/// Context Menu items, the style of the most recently selected shape is checked
+fn build_context(data: &CanvasData) -> MenuDesc<AppData> {
+    let style = data.selected_style();
+    let nothing_selected = style.is_none();
+    let same = |a: &Color, b: &Color| a.as_rgba_u32() == b.as_rgba_u32();
+    let style_item = |name: &'static str, change: StyleChange, checked: bool| {
+        MenuItem::new(LocalizedString::new(name), CVS_CTX_STYLE.with(change))
+            .selected_if(|| checked)
+            .disabled_if(|| nothing_selected)
+    };
+
+    let stroke = PALETTE.iter().fold(MenuDesc::new(LocalizedString::new("Stroke Colour")), |menu, (name, color)| {
+        let checked = style.is_some_and(|s| same(&s.stroke, color));
+        menu.append(style_item(name, StyleChange::Stroke(color.clone()), checked))
+    });
+    let width = STROKE_WIDTHS.iter().fold(MenuDesc::new(LocalizedString::new("Stroke Width")), |menu, &(name, width)| {
+        let checked = style.is_some_and(|s| s.width == width);
+        menu.append(style_item(name, StyleChange::Width(width), checked))
+    });
+    let no_fill = style_item("None", StyleChange::Fill(None), style.is_some_and(|s| s.fill.is_none()));
+    let fill = PALETTE.iter().fold(MenuDesc::new(LocalizedString::new("Fill")).append(no_fill), |menu, (name, color)| {
+        let checked = style.and_then(|s| s.fill.as_ref()).is_some_and(|fill| same(fill, color));
+        menu.append(style_item(name, StyleChange::Fill(Some(color.clone())), checked))
+    });
+    let count = data.selected.len();
+    let arrange = ARRANGEMENTS.iter().fold(MenuDesc::new(LocalizedString::new("Arrange")), |menu, &(name, how)| {
+        let menu = if how == Arrange::Top || how == Arrange::Across { menu.append_separator() } else { menu };
+        menu.append(MenuItem::new(LocalizedString::new(name), CVS_CTX_ARRANGE.with(how))
+            .disabled_if(|| count < how.needs()))
+    });
+    let opacity = OPACITIES.iter().fold(MenuDesc::new(LocalizedString::new("Opacity")), |menu, &(name, opacity)| {
+        let checked = style.is_some_and(|s| s.opacity == opacity);
+        menu.append(style_item(name, StyleChange::Opacity(opacity), checked))
+    });
 
-/// Context Menu items
-fn build_context<T:Data>() -> MenuDesc<T> {
     MenuDesc::empty()
         .append(MenuItem::new(
             LocalizedString::new("Deselect"),
@@ -199,6 +394,70 @@ fn build_context<T:Data>() -> MenuDesc<T> {
             LocalizedString::new("Resize"),
             CVS_CTX_RESIZE,
         ))
+        .append_separator()
+        .append(stroke)
+        .append(width)
+        .append(fill)
+        .append(opacity)
+        .append(arrange)
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("Bring to Front"),
+            CVS_CTX_FRONT,
+        ).disabled_if(|| nothing_selected))
+        .append(MenuItem::new(
+            LocalizedString::new("Send to Back"),
+            CVS_CTX_BACK,
+        ).disabled_if(|| nothing_selected))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("Cut"),
+            druid::commands::CUT,
+        ).disabled_if(|| nothing_selected))
+        .append(MenuItem::new(
+            LocalizedString::new("Copy"),
+            druid::commands::COPY,
+        ).disabled_if(|| nothing_selected))
+        .append(MenuItem::new(
+            LocalizedString::new("Paste"),
+            druid::commands::PASTE,
+        ))
+        .append(MenuItem::new(
+            LocalizedString::new("Duplicate"),
+            CVS_CTX_DUPLICATE,
+        ).disabled_if(|| nothing_selected))
+        .append(MenuItem::new(
+            LocalizedString::new("Delete"),
+            CVS_CTX_DELETE,
+        ).disabled_if(|| nothing_selected))
+        .append_separator()
+        .append(MenuItem::new(
+            LocalizedString::new("Fit All"),
+            CVS_FIT_ALL,
+        ))
+}
+
+/// Puts the selected shapes on the clipboard, in our own format and as SVG for other apps.
+/// Returns false when nothing is selected
+fn copy_selection(canvas: &CanvasData) -> bool {
+    let (shapes, svg) = match (canvas.selection_tsv(), canvas.selection_svg()) {
+        (Some(shapes), Some(svg)) => (shapes, svg),
+        _ => return false,
+    };
+    Application::global().clipboard().put_formats(&[
+        ClipboardFormat::new(SHAPES_FORMAT, shapes),
+        ClipboardFormat::new(ClipboardFormat::SVG, svg.clone()),
+        svg.into(),
+    ]);
+    true
+}
+
+/// Shapes copied from a drawing, in our own format or as plain text in the same format
+fn clipboard_shapes(clipboard: &Clipboard) -> Option<String> {
+    match clipboard.preferred_format(&[SHAPES_FORMAT]) {
+        Some(format) => clipboard.get_format(format).and_then(|bytes| String::from_utf8(bytes).ok()),
+        None => clipboard.get_string(),
+    }
 }
 
 struct Delegate;
@@ -206,7 +465,7 @@ struct Delegate;
 impl AppDelegate<AppData> for Delegate {
     fn command(
         &mut self,
-        ctx: &mut DelegateCtx,
+        _ctx: &mut DelegateCtx,
         _target: Target,
         cmd: &Command,
         data: &mut AppData,
@@ -214,42 +473,102 @@ impl AppDelegate<AppData> for Delegate {
     ) -> bool {
         match cmd {
             _ if cmd.is(CVS_CTX_DESELECT) => {
-                data.canvas.selected = None;
+                data.finish_resize();
+                data.update_history();
+                data.canvas.selected.clear();
+                false
+            },
+            _ if cmd.is(CVS_CTX_RESIZE) => {
+                data.canvas.open_resize();
+                false
+            },
+            _ if cmd.is(CVS_CTX_STYLE) => {
+                data.apply(|canvas| canvas.restyle(cmd.get_unchecked(CVS_CTX_STYLE)));
+                false
+            },
+            _ if cmd.is(CVS_CTX_FRONT) || cmd.is(CVS_CTX_BACK) => {
+                data.apply(|canvas| canvas.reorder(cmd.is(CVS_CTX_FRONT)));
+                false
+            },
+            _ if cmd.is(CVS_CTX_ARRANGE) => {
+                data.apply(|canvas| canvas.arrange(*cmd.get_unchecked(CVS_CTX_ARRANGE)));
+                false
+            },
+            _ if cmd.is(CVS_CTX_DUPLICATE) => {
+                data.apply(CanvasData::duplicate_selection);
+                false
+            },
+            _ if cmd.is(CVS_CTX_DELETE) => {
+                data.apply(CanvasData::delete_selection);
+                false
+            },
+            _ if cmd.is(druid::commands::COPY) => {
+                copy_selection(&data.canvas);
+                false
+            },
+            _ if cmd.is(druid::commands::CUT) => {
+                if copy_selection(&data.canvas) {
+                    data.apply(CanvasData::delete_selection);
+                }
+                false
+            },
+            _ if cmd.is(CVS_VIEW_GRID) => {
+                data.canvas.placement.show_grid = !data.canvas.placement.show_grid;
+                false
+            },
+            _ if cmd.is(CVS_VIEW_SNAP) => {
+                data.canvas.placement.snap = !data.canvas.placement.snap;
+                false
+            },
+            _ if cmd.is(CVS_VIEW_GUIDES) => {
+                data.canvas.placement.guides = !data.canvas.placement.guides;
+                false
+            },
+            _ if cmd.is(druid::commands::NEW_FILE) => {
+                data.apply(|canvas| canvas.replace_drawing(""));
+                false
+            },
+            _ if cmd.is(druid::commands::OPEN_FILE) => {
+                match std::fs::read_to_string(cmd.get_unchecked(druid::commands::OPEN_FILE).path()) {
+                    Ok(text) => data.apply(|canvas| canvas.replace_drawing(&text)),
+                    Err(error) => println!("Error: {}", error),
+                }
+                false
+            },
+            _ if cmd.is(druid::commands::SAVE_FILE) => {
+                if let Some(file) = cmd.get_unchecked(druid::commands::SAVE_FILE) {
+                    if let Err(error) = std::fs::write(file.path(), data.canvas.to_tsv()) {
+                        println!("Error: {}", error);
+                    }
+                }
                 false
             },
-            _ if cmd.is(CVS_CTX_RESIZE) && data.canvas.selected.is_some() && data.window_count == 1 => {
-                let popup = WindowDesc::new(build_popup)
-                    .window_size(POPUP_SIZE)
-                    .resizable(false)
-                    .title("resize");
-                ctx.new_window(popup);
+            _ if cmd.is(RECORD_ACTION) => {
+                data.action_log.record(cmd.get_unchecked(RECORD_ACTION).clone());
+                data.update_history();
                 false
             },
             _ if cmd.is(druid::commands::UNDO) => {
-                println!("UNDO CMD");
+                data.finish_resize();
+                if let Some(item) = data.action_log.undo() {
+                    data.canvas.restore(item.before.clone());
+                }
+                data.update_history();
                 false
             },
             _ if cmd.is(druid::commands::REDO) => {
-                println!("REDO CMD");
+                data.finish_resize();
+                if let Some(item) = data.action_log.redo() {
+                    data.canvas.restore(item.after.clone());
+                }
+                data.update_history();
                 false
             },
             _ => true
         }
     }
 
-    fn window_added(
-        &mut self,
-        _id: WindowId,
-        data: &mut AppData,
-        _env: &Env,
-        _ctx: &mut DelegateCtx,
-    ) {
-        data.window_count += 1;
-        if data.window_count > 1 {
-            data.canvas.enabled = false;
-        }
-    }
-
+    /// The drawing is saved when the window closes and loaded again on the next run
     fn window_removed(
         &mut self,
         _id: WindowId,
@@ -257,9 +576,8 @@ impl AppDelegate<AppData> for Delegate {
         _env: &Env,
         _ctx: &mut DelegateCtx,
     ) {
-        data.window_count -= 1;
-        if data.window_count == 1 {
-            data.canvas.enabled = true;
+        if let Err(error) = persist::store(data) {
+            println!("Error: {}", error);
         }
     }
 }
@@ -267,7 +585,7 @@ impl AppDelegate<AppData> for Delegate {
 /// # Action History
 /// This manages the undo and redo functionality in the struct
 /// position represents the current location in the list,
-/// if position is None there is nothing to redo, undo from end
+/// everything before it can be undone and everything from it on can be redone
 /// max_actions represents the maximum number of items to store before actions are removed
 /// action list is the actual history list
 
@@ -275,7 +593,7 @@ impl AppDelegate<AppData> for Delegate {
 pub struct ActionLog {
     position: usize,
     max_actions: usize,
-    action_list: Vector<usize> // TODO: Change to Vector<ActionItem> once implemented
+    action_list: Vector<ActionItem>
 }
 
 impl ActionLog {
@@ -283,9 +601,7 @@ impl ActionLog {
     pub fn new(max: usize) -> Self {
         ActionLog {
             position: 0,
-            /// Maxmimum number of actions the log can store
             max_actions: max,
-            /// Actual list of actions
             action_list: Vector::new()
         }
     }
@@ -294,263 +610,2675 @@ impl ActionLog {
     pub fn default() -> Self {
         ActionLog::new(10)
     }
+
+    /// Adds an action at the current position, anything that could have been redone is dropped
+    pub fn record(&mut self, item: ActionItem) {
+        if self.action_list.len() > self.position {
+            self.action_list.truncate(self.position);
+        }
+        self.action_list.push_back(item);
+        if self.action_list.len() > self.max_actions {
+            self.action_list.pop_front();
+        }
+        self.position = self.action_list.len();
+    }
+
+    /// Steps back, returning the action to reverse
+    pub fn undo(&mut self) -> Option<&ActionItem> {
+        if self.can_undo() {
+            self.position -= 1;
+            self.action_list.get(self.position)
+        } else {
+            None
+        }
+    }
+
+    /// Steps forward, returning the action to apply again
+    pub fn redo(&mut self) -> Option<&ActionItem> {
+        if self.can_redo() {
+            self.position += 1;
+            self.action_list.get(self.position - 1)
+        } else {
+            None
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.position > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.position < self.action_list.len()
+    }
 }
 
-/// The action item stores what action occured and an optional value
+/// Sent by the canvas once an action is complete, the delegate adds it to the log
+const RECORD_ACTION: Selector<ActionItem> = Selector::new("app.circles.record-action");
+
+/// The action item stores what action occured and the circles either side of it.
+/// The snapshots share their unchanged circles and cells, so keeping both is cheap.
 #[derive(Clone, Data)]
-struct ActionItem {
+pub struct ActionItem {
     action_type: ActionType,
-    radius: Option<f64>,
-    circle_id: usize,
+    before: custom::Snapshot,
+    after: custom::Snapshot,
 }
 
-/// implements the creation and adjustment methods
 impl ActionItem {
-    fn creation(circle_id: usize) -> Self {
-        ActionItem {
-            action_type: ActionType::Creation,
-            radius: None,
-            circle_id
-        }
-    }
-
-    fn adjustment(circle_id: usize, radius: f64) -> Self {
+    fn new(action_type: ActionType, before: custom::Snapshot, after: custom::Snapshot) -> Self {
         ActionItem {
-            action_type: ActionType::Adjustment,
-            radius: Some(radius),
-            circle_id
+            action_type,
+            before,
+            after,
         }
     }
 }
 
 /// The action type defines what actions can occur
-#[derive(Clone, Data, PartialEq)]
+#[derive(Clone, Copy, Data, PartialEq, Debug)]
 enum ActionType {
     Creation,
     Adjustment,
+    Move,
+    Restyle,
+    Reorder,
+    Deletion,
+    Duplication,
+    Paste,
+    Arrange,
+    Replace,
 }
 
-/// ## Custom widgets implemented in this app
-mod custom {
-    use super::*;
-    use druid::{Point, MouseButton, Size, kurbo};
+/// ## Shapes
+/// Everything the canvas can draw implements Shape, in document coordinates.
+/// Item holds any one of them, so a drawing can keep them together in one vector.
+mod shapes {
+    use super::{MIN_RADIUS, MAX_RADIUS};
+    use druid::{Data, Point, Rect, Size, Vec2, Color, RenderContext, PaintCtx};
+    use druid::kurbo::{self, BezPath, ParamCurveNearest as _, Shape as _};
+    use druid::im::Vector;
+
+    // the size given to new shapes, the radius of a circle
+    const DEFAULT_SIZE: f64 = 25.;
+    // half the width of the resize handle drawn on the selected shape
+    pub const HANDLE_SIZE: f64 = 4.;
+    // how close a click has to be to a line to hit it
+    const LINE_REACH: f64 = 4.;
+    // how far from the origin a shape read from text can be
+    const MAX_COORD: f64 = 1e6;
+    // the widest stroke read from text
+    const MAX_STROKE_WIDTH: f64 = 32.;
+
+    /// What a shape on the canvas has to be able to do
+    pub trait Shape {
+        /// The name shown on the toolbar and the overlay, also the first field when saved
+        fn name(&self) -> &'static str;
+
+        /// The smallest rectangle around the shape, not counting the stroke
+        fn bounds(&self) -> Rect;
+
+        /// Whether a point is inside the shape, or near enough to a line
+        fn contains(&self, pos: Point) -> bool;
+
+        /// The outline that is stroked and, for a closed shape, filled
+        fn path(&self) -> BezPath;
+
+        /// Whether the outline encloses an area that can be filled
+        fn is_closed(&self) -> bool {
+            true
+        }
+
+        /// Where the resize handle sits, the bottom right corner unless a shape has a better place
+        fn handle(&self) -> Point {
+            let bounds = self.bounds();
+            Point::new(bounds.x1, bounds.y1)
+        }
+
+        /// Resizes so the handle follows the mouse
+        fn resize_to(&mut self, pos: Point);
+
+        fn translate(&mut self, delta: Vec2);
+
+        /// The size set on the resize overlay, the radius of a circle and half the longest side of the rest
+        fn size(&self) -> f64 {
+            let bounds = self.bounds();
+            bounds.width().max(bounds.height()) / 2.
+        }
+
+        /// Scales the shape about its centre to a new size
+        fn set_size(&mut self, size: f64);
+
+        /// The numbers saved after the name, read back by Item::from_tsv
+        fn fields(&self) -> Vec<f64>;
+    }
+
+    /// The factor to scale a shape by to go from one size to another, within the size limits
+    fn scale_factor(from: f64, to: f64) -> f64 {
+        if from > 0. {
+            to.clamp(MIN_RADIUS, MAX_RADIUS) / from
+        } else {
+            1.
+        }
+    }
 
-    const RADIUS: f64 = 25.;
+    fn scale_about(pos: Point, centre: Point, scale: Vec2) -> Point {
+        let offset = pos - centre;
+        centre + Vec2::new(offset.x * scale.x, offset.y * scale.y)
+    }
 
-    /// Holds individual circle data, only implements Widget<CanvasData>
-    #[derive(Clone, Data, Lens)]
+    #[derive(Clone, Data)]
     pub struct Circle {
-        pos: Point,
-        index: usize,
+        centre: Point,
         radius: f64,
     }
 
-    impl Circle {
-        pub fn new(pos: Point, index: usize) -> Self {
-            Circle {
-                pos,
-                index,
-                radius: RADIUS,
-            }
+    impl Shape for Circle {
+        fn name(&self) -> &'static str {
+            "Circle"
         }
-    }
 
-    impl Widget<CanvasData> for Circle {
-        fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut CanvasData, _env: &Env) {}
+        fn bounds(&self) -> Rect {
+            Rect::from_center_size(self.centre, (self.radius * 2., self.radius * 2.))
+        }
 
-        fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, _event: &LifeCycle, _data: &CanvasData, _env: &Env) {}
+        fn contains(&self, pos: Point) -> bool {
+            (pos - self.centre).hypot() < self.radius
+        }
 
-        fn update(&mut self, ctx: &mut UpdateCtx, _old_data: &CanvasData, _data: &CanvasData, _env: &Env) {
-            ctx.request_paint()
+        fn path(&self) -> BezPath {
+            kurbo::Circle::new(self.centre, self.radius).into_bez_path(0.1)
         }
 
-        fn layout(&mut self, _ctx: &mut LayoutCtx, bc: &BoxConstraints, _data: &CanvasData, _env: &Env) -> Size {
-            let dim = self.radius *2.;
-            bc.constrain(Size::new(dim, dim))
+        /// On the right of the rim
+        fn handle(&self) -> Point {
+            self.centre + Vec2::new(self.radius, 0.)
         }
 
-        fn paint(&mut self, ctx: &mut PaintCtx, data: &CanvasData, _env: &Env) {
-            let shape = kurbo::Circle::new(self.pos, self.radius);
-            ctx.stroke(shape, &Color::BLACK, 2.);
-            if let Some(i) = data.selected {
-                if i == self.index {
-                    ctx.fill(shape, &Color::BLACK.with_alpha(0.3));
-                }
-            }
+        fn resize_to(&mut self, pos: Point) {
+            self.radius = (pos - self.centre).hypot().clamp(MIN_RADIUS, MAX_RADIUS);
         }
-    }
 
+        fn translate(&mut self, delta: Vec2) {
+            self.centre += delta;
+        }
 
+        fn size(&self) -> f64 {
+            self.radius
+        }
 
-    /// This holds the data for the canvas.
-    /// This is created in AppData. use a lens on the Canvas widget from Appdata
-    /// much like lensing a string to a label
-    #[derive(Clone, Data, Lens)]
-    pub struct CanvasData {
-        pub circles: Vector<custom::Circle>,
-        pub selected: Option<usize>,
-        pub enabled: bool,
+        fn set_size(&mut self, size: f64) {
+            self.radius = size.clamp(MIN_RADIUS, MAX_RADIUS);
+        }
+
+        fn fields(&self) -> Vec<f64> {
+            vec![self.centre.x, self.centre.y, self.radius]
+        }
     }
 
-    impl CanvasData {
-        pub fn new() -> Self {
-            CanvasData {
-                circles: Vector::new(),
-                selected: None,
-                enabled: true,
-            }
+    #[derive(Clone, Data)]
+    pub struct Ellipse {
+        centre: Point,
+        radii: Vec2,
+    }
+
+    impl Shape for Ellipse {
+        fn name(&self) -> &'static str {
+            "Ellipse"
         }
 
-        fn add_circle(&mut self, pos: Point) {
-            let v_len = self.circles.len();
-            self.circles.push_back(Circle::new(pos, v_len));
+        fn bounds(&self) -> Rect {
+            Rect::from_center_size(self.centre, Size::new(self.radii.x * 2., self.radii.y * 2.))
         }
 
-        /// This function will update the radius of the currently selected item if it exists.
-        /// It works given an f64 input from 0 to 1 and references the max and min radius
-        /// Use with a custom controller
-        pub fn update_radius(&mut self, radius: f64) {
-            if let Some(i) = self.selected {
-                self.circles[i].radius = radius;
-            }
+        fn contains(&self, pos: Point) -> bool {
+            let offset = pos - self.centre;
+            (offset.x / self.radii.x).powi(2) + (offset.y / self.radii.y).powi(2) < 1.
+        }
+
+        fn path(&self) -> BezPath {
+            kurbo::Ellipse::new(self.centre, self.radii, 0.).into_bez_path(0.1)
+        }
+
+        fn resize_to(&mut self, pos: Point) {
+            let offset = pos - self.centre;
+            self.radii = Vec2::new(
+                offset.x.abs().clamp(MIN_RADIUS, MAX_RADIUS),
+                offset.y.abs().clamp(MIN_RADIUS, MAX_RADIUS),
+            );
+        }
+
+        fn translate(&mut self, delta: Vec2) {
+            self.centre += delta;
+        }
+
+        fn set_size(&mut self, size: f64) {
+            self.radii *= scale_factor(self.size(), size);
         }
 
-        pub fn update_specific(&mut self, radius: f64, index: usize) {
-            self.circles[index].radius = radius
+        fn fields(&self) -> Vec<f64> {
+            vec![self.centre.x, self.centre.y, self.radii.x, self.radii.y]
         }
     }
 
-    /// The canvas widget requires a lens to CanvasData
-    /// For example:
-    /// ```no_run
-    /// struct AppData{
-    ///     canvas: custom::CanvasData,
-    /// }
-    ///
-    /// let canvas = custom::Canvas.lens(AppData::canvas);
-    /// ```
-    pub struct Canvas;
+    #[derive(Clone, Data)]
+    pub struct Rectangle {
+        rect: Rect,
+    }
 
-    impl Widget<CanvasData> for Canvas {
-        fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut CanvasData, _env: &Env) {
+    impl Shape for Rectangle {
+        fn name(&self) -> &'static str {
+            "Rectangle"
+        }
 
-            // Must request focus to use keyboard widgets
-            ctx.request_focus();
+        fn bounds(&self) -> Rect {
+            self.rect
+        }
 
-            // straightforward way to disable direct interaction
-            if data.enabled {
-                match event {
-                    Event::MouseDown(e) => {
-                        // println!("{:?} pressed at {}", e.button , e.pos);
-                        // println!("Canvas is enabled: {}", data.enabled);
-
-                        match e.button {
-                            MouseButton::Left => {
-                                let mut nearest: Option<(usize, f64)> = None;
-                                for c in &data.circles {
-                                    let distance = (c.pos - e.pos).hypot();
-                                    if distance < c.radius {
-                                        println!("ITEM SELECTED = {}", c.index);
-                                        if nearest.is_none() || nearest.unwrap().1 > distance {
-                                            nearest = Some((c.index, distance));
-                                        }
-                                    }
-                                }
+        fn contains(&self, pos: Point) -> bool {
+            self.rect.contains(pos)
+        }
 
-                                if let Some((index, _)) = nearest {
-                                    // Deselect by clicking circle again
-                                    if data.selected == Some(index) {
-                                        data.selected = None;
-                                    } else {
-                                        data.selected = Some(index);
-                                    }
-                                } else {
-                                    data.selected = None;
-                                    data.add_circle(e.pos);
-                                }
-                                ctx.request_paint()
-                            },
+        fn path(&self) -> BezPath {
+            self.rect.into_bez_path(0.1)
+        }
 
-                            MouseButton::Right => {
-                                let menu = ContextMenu::new(
-                                    build_context::<AppData>()
-                                    , e.pos
-                                );
-                                ctx.show_context_menu(menu);
-                            },
-                            _ => ()
-                        }
-                    },
-                    // Deselection through escape
-                    Event::KeyDown(e) => {
-                        if e.key_code == KeyCode::Escape {
-                            data.selected = None;
-                        }
-                    },
-                    _ => (),
-                }
-            }
+        /// The top left corner stays where it is
+        fn resize_to(&mut self, pos: Point) {
+            let (x0, y0) = (self.rect.x0, self.rect.y0);
+            self.rect.x1 = pos.x.clamp(x0 + MIN_RADIUS * 2., x0 + MAX_RADIUS * 2.);
+            self.rect.y1 = pos.y.clamp(y0 + MIN_RADIUS * 2., y0 + MAX_RADIUS * 2.);
         }
 
-        // no functionality
-        fn lifecycle(
-            &mut self,
-            _ctx: &mut LifeCycleCtx,
-            _ev: &LifeCycle,
-            _data: &CanvasData,
-            _env: &Env,
-        ) {}
+        fn translate(&mut self, delta: Vec2) {
+            self.rect = self.rect + delta;
+        }
 
-        // no functionality
-        fn update(
-            &mut self,
-            ctx: &mut UpdateCtx,
-            old: &CanvasData,
-            new: &CanvasData,
-            env: &Env,
-        ) {
-            for c in &new.circles {
-                //Can this be done without cloning?
-                c.clone().update(ctx, old, new, env)
-            }
+        fn set_size(&mut self, size: f64) {
+            let scale = scale_factor(self.size(), size);
+            self.rect = Rect::from_center_size(self.rect.center(), self.rect.size() * scale);
         }
 
-        // sets boundaries
-        fn layout(
-            &mut self,
-            _ctx: &mut LayoutCtx,
-            bc: &BoxConstraints,
-            _data: &CanvasData,
-            _env: &Env,
-        ) -> Size {
-            bc.max()
+        fn fields(&self) -> Vec<f64> {
+            vec![self.rect.x0, self.rect.y0, self.rect.x1, self.rect.y1]
         }
+    }
 
-        // Paint the widget
-        fn paint(&mut self, ctx: &mut PaintCtx, data: &CanvasData, env: &Env) {
-            // paint goes here
-            let rect = ctx.size().to_rect();
-            ctx.clip(rect);
-            ctx.fill(rect, &Color::grey(0.4));
+    #[derive(Clone, Data)]
+    pub struct Line {
+        start: Point,
+        end: Point,
+    }
+
+    impl Shape for Line {
+        fn name(&self) -> &'static str {
+            "Line"
+        }
+
+        fn bounds(&self) -> Rect {
+            Rect::from_points(self.start, self.end)
+        }
+
+        fn contains(&self, pos: Point) -> bool {
+            kurbo::Line::new(self.start, self.end).nearest(pos, 0.).1 <= LINE_REACH * LINE_REACH
+        }
 
-            for c in &data.circles {
-                //Can this be done without cloning?
-                c.clone().paint(ctx, data, env)
+        fn path(&self) -> BezPath {
+            let mut path = BezPath::new();
+            path.move_to(self.start);
+            path.line_to(self.end);
+            path
+        }
+
+        fn is_closed(&self) -> bool {
+            false
+        }
+
+        /// The end of the line
+        fn handle(&self) -> Point {
+            self.end
+        }
+
+        fn resize_to(&mut self, pos: Point) {
+            let offset = pos - self.start;
+            if offset.hypot() > 0. {
+                let length = offset.hypot().clamp(MIN_RADIUS * 2., MAX_RADIUS * 2.);
+                self.end = self.start + offset.normalize() * length;
             }
         }
-    }
-}
 
+        fn translate(&mut self, delta: Vec2) {
+            self.start += delta;
+            self.end += delta;
+        }
 
+        fn size(&self) -> f64 {
+            (self.end - self.start).hypot() / 2.
+        }
 
+        fn set_size(&mut self, size: f64) {
+            let scale = Vec2::new(1., 1.) * scale_factor(self.size(), size);
+            let centre = self.start.midpoint(self.end);
+            self.start = scale_about(self.start, centre, scale);
+            self.end = scale_about(self.end, centre, scale);
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        fn fields(&self) -> Vec<f64> {
+            vec![self.start.x, self.start.y, self.end.x, self.end.y]
+        }
+    }
+
+    #[derive(Clone, Data)]
+    pub struct Polygon {
+        points: Vector<Point>,
+    }
+
+    impl Polygon {
+        fn scale(&mut self, centre: Point, scale: Vec2) {
+            self.points = self.points.iter().map(|&p| scale_about(p, centre, scale)).collect();
+        }
+    }
+
+    impl Shape for Polygon {
+        fn name(&self) -> &'static str {
+            "Polygon"
+        }
+
+        fn bounds(&self) -> Rect {
+            let first = Rect::from_origin_size(self.points[0], (0., 0.));
+            self.points.iter().fold(first, |bounds, &p| bounds.union_pt(p))
+        }
+
+        /// Counts the edges crossed going right from the point, inside if it is odd
+        fn contains(&self, pos: Point) -> bool {
+            let mut inside = false;
+            let mut previous = self.points[self.points.len() - 1];
+            for &p in self.points.iter() {
+                if (p.y > pos.y) != (previous.y > pos.y)
+                    && pos.x < p.x + (pos.y - p.y) * (previous.x - p.x) / (previous.y - p.y) {
+                    inside = !inside;
+                }
+                previous = p;
+            }
+            inside
+        }
+
+        fn path(&self) -> BezPath {
+            let mut path = BezPath::new();
+            path.move_to(self.points[0]);
+            for &p in self.points.iter().skip(1) {
+                path.line_to(p);
+            }
+            path.close_path();
+            path
+        }
+
+        /// Stretches the polygon, the top left of its bounds stays where it is
+        fn resize_to(&mut self, pos: Point) {
+            let bounds = self.bounds();
+            let stretch = |from: f64, to: f64| {
+                if from > 0. { to.clamp(MIN_RADIUS * 2., MAX_RADIUS * 2.) / from } else { 1. }
+            };
+            let scale = Vec2::new(
+                stretch(bounds.width(), pos.x - bounds.x0),
+                stretch(bounds.height(), pos.y - bounds.y0),
+            );
+            self.scale(bounds.origin(), scale);
+        }
+
+        fn translate(&mut self, delta: Vec2) {
+            self.points = self.points.iter().map(|&p| p + delta).collect();
+        }
+
+        fn set_size(&mut self, size: f64) {
+            let scale = Vec2::new(1., 1.) * scale_factor(self.size(), size);
+            self.scale(self.bounds().center(), scale);
+        }
+
+        /// The points as x and y pairs
+        fn fields(&self) -> Vec<f64> {
+            self.points.iter().flat_map(|p| vec![p.x, p.y]).collect()
+        }
+    }
+
+    /// What a click on an empty part of the canvas does, picked from the toolbar
+    #[derive(Clone, Copy, Data, PartialEq, Debug)]
+    pub enum Tool {
+        /// Drags out a rubber band to select shapes rather than drawing one
+        Select,
+        Circle,
+        Ellipse,
+        Rectangle,
+        Line,
+        Polygon,
+    }
+
+    impl Tool {
+        pub const ALL: [Tool; 6] = [Tool::Select, Tool::Circle, Tool::Ellipse, Tool::Rectangle, Tool::Line, Tool::Polygon];
+
+        pub fn name(self) -> &'static str {
+            match self {
+                Tool::Select => "Select",
+                Tool::Circle => "Circle",
+                Tool::Ellipse => "Ellipse",
+                Tool::Rectangle => "Rectangle",
+                Tool::Line => "Line",
+                Tool::Polygon => "Polygon",
+            }
+        }
+    }
+
+    /// How a shape is painted
+    #[derive(Clone, Data)]
+    pub struct Style {
+        pub stroke: Color,
+        pub width: f64,
+        // closed shapes are left empty without a fill
+        pub fill: Option<Color>,
+        // applies to the stroke and the fill
+        pub opacity: f64,
+    }
+
+    impl Default for Style {
+        fn default() -> Self {
+            Style {
+                stroke: Color::BLACK,
+                width: 2.,
+                fill: None,
+                opacity: 1.,
+            }
+        }
+    }
+
+    /// A change to one part of a style, picked from the context menu
+    #[derive(Clone, Debug)]
+    pub enum StyleChange {
+        Stroke(Color),
+        Width(f64),
+        Fill(Option<Color>),
+        Opacity(f64),
+    }
+
+    impl Style {
+        pub fn apply(&mut self, change: &StyleChange) {
+            match change {
+                StyleChange::Stroke(color) => self.stroke = color.clone(),
+                StyleChange::Width(width) => self.width = *width,
+                StyleChange::Fill(color) => self.fill = color.clone(),
+                StyleChange::Opacity(opacity) => self.opacity = *opacity,
+            }
+        }
+
+        /// A colour as it is painted, faded by the opacity
+        fn faded(&self, color: &Color) -> Color {
+            let (r, g, b, a) = color.as_rgba();
+            Color::rgba(r, g, b, a * self.opacity)
+        }
+
+        /// Stroke colour, width, fill colour or none and opacity
+        fn fields(&self) -> Vec<String> {
+            let hex = |color: &Color| format!("#{:08x}", color.as_rgba_u32());
+            vec![
+                hex(&self.stroke),
+                self.width.to_string(),
+                self.fill.as_ref().map_or("none".to_string(), hex),
+                self.opacity.to_string(),
+            ]
+        }
+
+        /// The style as SVG presentation attributes, an open shape is never filled
+        fn svg_attributes(&self, closed: bool) -> String {
+            let hex = |color: &Color| {
+                let (r, g, b, a) = color.as_rgba8();
+                (format!("#{:02x}{:02x}{:02x}", r, g, b), a as f64 / 255. * self.opacity)
+            };
+            let (stroke, stroke_opacity) = hex(&self.stroke);
+            let fill = match self.fill.as_ref().filter(|_| closed) {
+                Some(fill) => {
+                    let (fill, fill_opacity) = hex(fill);
+                    format!("fill=\"{}\" fill-opacity=\"{}\"", fill, fill_opacity)
+                },
+                None => "fill=\"none\"".to_string(),
+            };
+            format!("stroke=\"{}\" stroke-opacity=\"{}\" stroke-width=\"{}\" {}",
+                    stroke, stroke_opacity, self.width, fill)
+        }
+
+        fn from_fields(fields: &[&str]) -> Option<Style> {
+            let color = |field: &str| {
+                let hex = field.strip_prefix('#')?;
+                u32::from_str_radix(hex, 16).ok().map(Color::from_rgba32_u32)
+            };
+            let number = |field: &str| field.parse::<f64>().ok().filter(|n| n.is_finite() && *n >= 0.);
+            match fields {
+                [stroke, width, fill, opacity] => Some(Style {
+                    stroke: color(stroke)?,
+                    width: number(width)?.min(MAX_STROKE_WIDTH),
+                    fill: if *fill == "none" { None } else { Some(color(fill)?) },
+                    opacity: number(opacity)?.min(1.),
+                }),
+                _ => None,
+            }
+        }
+    }
+
+    /// Whichever shape an item is
+    #[derive(Clone, Data)]
+    enum AnyShape {
+        Circle(Circle),
+        Ellipse(Ellipse),
+        Rectangle(Rectangle),
+        Line(Line),
+        Polygon(Polygon),
+    }
+
+    /// Any one shape on the canvas, with its style
+    #[derive(Clone, Data)]
+    pub struct Item {
+        shape: AnyShape,
+        pub style: Style,
+    }
+
+    impl Item {
+        fn with_shape(shape: AnyShape) -> Item {
+            Item {
+                shape,
+                style: Style::default(),
+            }
+        }
+
+        /// A shape of the default size for a click with the tool.
+        /// The handle lands on the click when the mouse is dragged, so a drag sizes the new shape.
+        /// Polygons are built a point at a click instead and the select tool draws nothing,
+        /// so there is none for them.
+        pub fn new(tool: Tool, pos: Point) -> Option<Item> {
+            let shape = match tool {
+                Tool::Circle => AnyShape::Circle(Circle { centre: pos, radius: DEFAULT_SIZE }),
+                Tool::Ellipse => AnyShape::Ellipse(Ellipse {
+                    centre: pos,
+                    radii: Vec2::new(DEFAULT_SIZE, DEFAULT_SIZE * 0.6),
+                }),
+                Tool::Rectangle => AnyShape::Rectangle(Rectangle {
+                    rect: Rect::from_origin_size(pos, (DEFAULT_SIZE * 2., DEFAULT_SIZE * 1.2)),
+                }),
+                Tool::Line => AnyShape::Line(Line { start: pos, end: pos + Vec2::new(DEFAULT_SIZE * 2., 0.) }),
+                Tool::Select | Tool::Polygon => return None,
+            };
+            Some(Item::with_shape(shape))
+        }
+
+        /// A polygon through the points, None if there are too few to enclose anything
+        pub fn polygon(points: Vector<Point>) -> Option<Item> {
+            if points.len() < 3 {
+                return None;
+            }
+            Some(Item::with_shape(AnyShape::Polygon(Polygon { points })))
+        }
+
+        pub fn shape(&self) -> &dyn Shape {
+            match &self.shape {
+                AnyShape::Circle(s) => s,
+                AnyShape::Ellipse(s) => s,
+                AnyShape::Rectangle(s) => s,
+                AnyShape::Line(s) => s,
+                AnyShape::Polygon(s) => s,
+            }
+        }
+
+        pub fn shape_mut(&mut self) -> &mut dyn Shape {
+            match &mut self.shape {
+                AnyShape::Circle(s) => s,
+                AnyShape::Ellipse(s) => s,
+                AnyShape::Rectangle(s) => s,
+                AnyShape::Line(s) => s,
+                AnyShape::Polygon(s) => s,
+            }
+        }
+
+        /// The area painted for the shape, including the stroke and the resize handle
+        pub fn paint_bounds(&self) -> Rect {
+            let margin = (HANDLE_SIZE + 1.).max(self.style.width / 2. + 1.);
+            self.shape().bounds().inflate(margin, margin)
+        }
+
+        pub fn paint(&self, ctx: &mut PaintCtx, selected: bool) {
+            let shape = self.shape();
+            let style = &self.style;
+            let path = shape.path();
+            if let Some(fill) = style.fill.as_ref().filter(|_| shape.is_closed()) {
+                ctx.fill(&path, &style.faded(fill));
+            }
+            ctx.stroke(&path, &style.faded(&style.stroke), style.width);
+            if selected {
+                if shape.is_closed() {
+                    ctx.fill(&path, &Color::BLACK.with_alpha(0.3));
+                } else {
+                    ctx.stroke(&path, &Color::BLACK.with_alpha(0.3), style.width.max(HANDLE_SIZE * 2.));
+                }
+                let handle = Rect::from_center_size(shape.handle(), (HANDLE_SIZE * 2., HANDLE_SIZE * 2.));
+                ctx.fill(handle, &Color::WHITE);
+                ctx.stroke(handle, &Color::BLACK, 1.);
+            }
+        }
+
+        /// The name, the style and the fields of the shape separated by tabs
+        pub fn to_tsv(&self) -> String {
+            let shape = self.shape();
+            let mut line = shape.name().to_string();
+            for field in self.style.fields() {
+                line.push('\t');
+                line.push_str(&field);
+            }
+            for field in shape.fields() {
+                line.push_str(&format!("\t{}", field));
+            }
+            line
+        }
+
+        /// The shape as an SVG path element, for other apps
+        pub fn to_svg(&self) -> String {
+            let shape = self.shape();
+            format!("<path d=\"{}\" {}/>", shape.path().to_svg(), self.style.svg_attributes(shape.is_closed()))
+        }
+
+        /// Reads a line written by to_tsv, None if it can't be read.
+        /// A line saved before shapes had a style goes straight from the name to the shape.
+        /// Shapes with no size or far off the canvas are rejected, others are brought within the size limits
+        pub fn from_tsv(line: &str) -> Option<Item> {
+            let mut fields: Vec<&str> = line.split('\t').collect();
+            let name = fields.remove(0);
+            let style = if fields.first().is_some_and(|f| f.starts_with('#')) {
+                let style = Style::from_fields(fields.get(..4)?)?;
+                fields.drain(..4);
+                style
+            } else {
+                Style::default()
+            };
+            let numbers = fields.iter().map(|f| f.parse::<f64>().ok().filter(|n| n.abs() <= MAX_COORD))
+                .collect::<Option<Vec<f64>>>()?;
+            let shape = match (name, numbers.as_slice()) {
+                ("Circle", &[x, y, radius]) if radius > 0. => AnyShape::Circle(Circle { centre: Point::new(x, y), radius }),
+                ("Ellipse", &[x, y, rx, ry]) if rx > 0. && ry > 0. => AnyShape::Ellipse(Ellipse {
+                    centre: Point::new(x, y),
+                    radii: Vec2::new(rx, ry),
+                }),
+                ("Rectangle", &[x0, y0, x1, y1]) if x0 != x1 && y0 != y1 => AnyShape::Rectangle(Rectangle {
+                    rect: Rect::new(x0, y0, x1, y1).abs(),
+                }),
+                ("Line", &[x0, y0, x1, y1]) if (x0, y0) != (x1, y1) => AnyShape::Line(Line {
+                    start: Point::new(x0, y0),
+                    end: Point::new(x1, y1),
+                }),
+                // polygons are drawn point by point so have no size limits, but still need an area
+                ("Polygon", points) if points.len().is_multiple_of(2) => {
+                    let item = Item::polygon(points.chunks(2).map(|p| Point::new(p[0], p[1])).collect())?;
+                    let bounds = item.shape().bounds();
+                    if bounds.width() == 0. || bounds.height() == 0. {
+                        return None;
+                    }
+                    return Some(Item { style, ..item });
+                },
+                _ => return None,
+            };
+            let mut item = Item { shape, style };
+            let handle = item.shape().handle();
+            item.shape_mut().resize_to(handle);
+            Some(item)
+        }
+    }
+}
+
+/// ## Custom widgets implemented in this app
+mod custom {
+    use super::*;
+    use druid::{Point, MouseButton, Size, Vec2, Rect, WidgetPod, Affine, KeyEvent};
+    use druid::kurbo::{self, BezPath};
+    use druid::im::{self, OrdSet};
+    use super::shapes::{Shape, Item, Tool, Style, StyleChange, HANDLE_SIZE};
+
+    // how far the view can be zoomed out and in
+    const MIN_ZOOM: f64 = 0.05;
+    const MAX_ZOOM: f64 = 20.;
+    // the zoom factor for one notch of the mouse wheel
+    const ZOOM_STEP: f64 = 1.1;
+    // the size of one notch, as reported by the platform
+    const WHEEL_NOTCH: f64 = 120.;
+    // how far the mouse moves before a click becomes a drag
+    const DRAG_THRESHOLD: f64 = 3.;
+    // how far down and to the right a duplicate is placed from the original
+    const DUPLICATE_OFFSET: f64 = 10.;
+    // the spatial index splits the canvas into square cells of this size
+    const CELL_SIZE: f64 = 100.;
+    // a shape over more cells than this is kept aside and checked every time instead
+    const MAX_SHAPE_CELLS: f64 = 256.;
+    // how far an arrow key moves the selected shapes, and the keyboard cursor in screen pixels.
+    // Shift moves ten times as far
+    const NUDGE: f64 = 1.;
+    const CURSOR_STEP: f64 = 10.;
+    // how much + and - change the size of the selected shapes, ten times as much with shift
+    const SIZE_STEP: f64 = 1.;
+    // the length of the arms of the keyboard cursor, in screen pixels
+    const CURSOR_SIZE: f64 = 8.;
+    // the distance between lines of the grid, and the closest they are painted in screen pixels
+    const GRID_SPACING: f64 = 20.;
+    const GRID_MIN_GAP: f64 = 8.;
+    // how close in screen pixels an edge or centre comes to another shape's before lining up
+    const GUIDE_REACH: f64 = 6.;
+    const GUIDE_COLOR: Color = Color::rgb8(0xff, 0x40, 0xc0);
+
+    /// ## Spatial Index
+    /// A uniform grid over the canvas, each cell lists the shapes whose bounds overlap it.
+    /// Hit-testing and painting only look at the cells under the point or area in question
+    /// rather than at every shape. It is built from persistent collections like the shapes,
+    /// so keeping a copy with every undo step costs next to nothing.
+    /// The edges of the shapes are counted as well, so the bounds of the whole drawing are at hand
+    /// without going through every shape.
+    #[derive(Clone, Data)]
+    pub struct SpatialGrid {
+        cells: im::HashMap<(i64, i64), Vector<usize>>,
+        // shapes too big to list in every cell they cover
+        oversized: OrdSet<usize>,
+        // how many shapes have each left, top, right and bottom edge
+        lefts: im::OrdMap<Edge, usize>,
+        tops: im::OrdMap<Edge, usize>,
+        rights: im::OrdMap<Edge, usize>,
+        bottoms: im::OrdMap<Edge, usize>,
+    }
+
+    /// A coordinate that can be sorted, the bounds of a shape are never NaN
+    #[derive(Clone, Copy, Debug)]
+    struct Edge(f64);
+
+    impl PartialEq for Edge {
+        fn eq(&self, other: &Self) -> bool {
+            self.cmp(other) == std::cmp::Ordering::Equal
+        }
+    }
+
+    impl Eq for Edge {}
+
+    impl PartialOrd for Edge {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Edge {
+        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            self.0.total_cmp(&other.0)
+        }
+    }
+
+    /// Adds one to the count of shapes with an edge, or takes one away
+    fn count_edge(edges: &mut im::OrdMap<Edge, usize>, at: f64, add: bool) {
+        let count = edges.get(&Edge(at)).copied().unwrap_or(0);
+        match (add, count) {
+            (true, _) => { edges.insert(Edge(at), count + 1); },
+            (false, 0 | 1) => { edges.remove(&Edge(at)); },
+            (false, _) => { edges.insert(Edge(at), count - 1); },
+        }
+    }
+
+    impl SpatialGrid {
+        pub fn new() -> Self {
+            SpatialGrid {
+                cells: im::HashMap::new(),
+                oversized: OrdSet::new(),
+                lefts: im::OrdMap::new(),
+                tops: im::OrdMap::new(),
+                rights: im::OrdMap::new(),
+                bottoms: im::OrdMap::new(),
+            }
+        }
+
+        fn count_edges(&mut self, bounds: Rect, add: bool) {
+            count_edge(&mut self.lefts, bounds.x0, add);
+            count_edge(&mut self.tops, bounds.y0, add);
+            count_edge(&mut self.rights, bounds.x1, add);
+            count_edge(&mut self.bottoms, bounds.y1, add);
+        }
+
+        /// The area covered by everything inserted, None when the grid is empty
+        pub fn bounds(&self) -> Option<Rect> {
+            Some(Rect::new(
+                self.lefts.get_min()?.0.0,
+                self.tops.get_min()?.0.0,
+                self.rights.get_max()?.0.0,
+                self.bottoms.get_max()?.0.0,
+            ))
+        }
+
+        /// The cells a rectangle overlaps, as inclusive ranges of columns and rows
+        fn cell_range(rect: Rect) -> ((i64, i64), (i64, i64)) {
+            let cell = |v: f64| (v / CELL_SIZE).floor() as i64;
+            ((cell(rect.x0), cell(rect.x1)), (cell(rect.y0), cell(rect.y1)))
+        }
+
+        /// How many cells a range covers, in floats as a huge shape can cover more than fit in an i64
+        fn cell_count(((x0, x1), (y0, y1)): ((i64, i64), (i64, i64))) -> f64 {
+            (x1 as f64 - x0 as f64 + 1.) * (y1 as f64 - y0 as f64 + 1.)
+        }
+
+        pub fn insert(&mut self, index: usize, bounds: Rect) {
+            self.count_edges(bounds, true);
+            let range = SpatialGrid::cell_range(bounds);
+            if SpatialGrid::cell_count(range) > MAX_SHAPE_CELLS {
+                self.oversized.insert(index);
+                return;
+            }
+            let ((x0, x1), (y0, y1)) = range;
+            for x in x0..=x1 {
+                for y in y0..=y1 {
+                    self.cells.entry((x, y)).or_default().push_back(index);
+                }
+            }
+        }
+
+        /// Takes a shape out of the cells it was inserted into, the bounds must be the same
+        pub fn remove(&mut self, index: usize, bounds: Rect) {
+            self.count_edges(bounds, false);
+            let range = SpatialGrid::cell_range(bounds);
+            if SpatialGrid::cell_count(range) > MAX_SHAPE_CELLS {
+                self.oversized.remove(&index);
+                return;
+            }
+            let ((x0, x1), (y0, y1)) = range;
+            for x in x0..=x1 {
+                for y in y0..=y1 {
+                    if let Some(cell) = self.cells.get_mut(&(x, y)) {
+                        cell.retain(|&i| i != index);
+                        if cell.is_empty() {
+                            self.cells.remove(&(x, y));
+                        }
+                    }
+                }
+            }
+        }
+
+        /// The shapes that may contain the point
+        pub fn at_point(&self, pos: Point) -> impl Iterator<Item = &usize> {
+            let ((x, _), (y, _)) = SpatialGrid::cell_range(Rect::from_origin_size(pos, Size::ZERO));
+            self.cells.get(&(x, y)).into_iter().flatten().chain(self.oversized.iter())
+        }
+
+        /// The shapes that may overlap the area, in drawing order and without repeats
+        pub fn in_rect(&self, rect: Rect) -> Vec<usize> {
+            let range = SpatialGrid::cell_range(rect);
+            let ((x0, x1), (y0, y1)) = range;
+            let mut found: Vec<usize> = if SpatialGrid::cell_count(range) > self.cells.len() as f64 {
+                // a large area is quicker to check cell by cell than to look up every position in it
+                self.cells.iter()
+                    .filter(|((x, y), _)| (x0..=x1).contains(x) && (y0..=y1).contains(y))
+                    .flat_map(|(_, cell)| cell.iter().copied())
+                    .collect()
+            } else {
+                let mut found = Vec::new();
+                for x in x0..=x1 {
+                    for y in y0..=y1 {
+                        if let Some(cell) = self.cells.get(&(x, y)) {
+                            found.extend(cell.iter().copied());
+                        }
+                    }
+                }
+                found
+            };
+            found.extend(self.oversized.iter().copied());
+            found.sort_unstable();
+            found.dedup();
+            found
+        }
+    }
+
+    /// The shapes at one point in time along with their index, kept by the action history
+    #[derive(Clone, Data)]
+    pub struct Snapshot {
+        shapes: Vector<Item>,
+        grid: SpatialGrid,
+    }
+
+    /// ## Viewport
+    /// Shapes are kept in document coordinates, the viewport maps them onto the canvas.
+    /// origin is the document point shown at the top left corner of the canvas,
+    /// zoom is the number of screen pixels per document unit.
+    #[derive(Clone, Copy, Data, PartialEq)]
+    pub struct Viewport {
+        origin: Point,
+        zoom: f64,
+    }
+
+    impl Default for Viewport {
+        fn default() -> Self {
+            Viewport {
+                origin: Point::ORIGIN,
+                zoom: 1.,
+            }
+        }
+    }
+
+    impl Viewport {
+        pub fn to_doc(self, screen: Point) -> Point {
+            self.origin + screen.to_vec2() / self.zoom
+        }
+
+        pub fn to_screen(self, doc: Point) -> Point {
+            ((doc - self.origin) * self.zoom).to_point()
+        }
+
+        pub fn doc_rect(&self, screen: Rect) -> Rect {
+            Rect::from_points(self.to_doc(screen.origin()), self.to_doc(Point::new(screen.x1, screen.y1)))
+        }
+
+        pub fn screen_rect(&self, doc: Rect) -> Rect {
+            Rect::from_points(self.to_screen(doc.origin()), self.to_screen(Point::new(doc.x1, doc.y1)))
+        }
+
+        /// The transform from document to screen, for painting
+        pub fn affine(&self) -> Affine {
+            Affine::scale(self.zoom) * Affine::translate(-self.origin.to_vec2())
+        }
+
+        /// Zooms by a factor, keeping the document point under the cursor where it is
+        pub fn zoom_at(&mut self, screen: Point, factor: f64) {
+            let doc = self.to_doc(screen);
+            self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+            self.origin = doc - screen.to_vec2() / self.zoom;
+        }
+
+        /// Moves the view along with a drag of the given screen distance
+        pub fn pan(&mut self, screen: Vec2) {
+            self.origin -= screen / self.zoom;
+        }
+
+        /// Pans as little as possible to bring a document area into view on a canvas of the
+        /// given size, the top left corner is shown if it doesn't fit
+        pub fn reveal(&mut self, doc: Rect, size: Size) {
+            let visible = self.doc_rect(size.to_rect());
+            let shift = |low: f64, high: f64, start: f64, end: f64| {
+                if low < start {
+                    low - start
+                } else if high > end {
+                    (high - end).min(low - start)
+                } else {
+                    0.
+                }
+            };
+            self.origin += Vec2::new(
+                shift(doc.x0, doc.x1, visible.x0, visible.x1),
+                shift(doc.y0, doc.y1, visible.y0, visible.y1),
+            );
+        }
+    }
+
+    /// The grid and the guides that help place shapes, not part of the action history
+    #[derive(Clone, Copy, Data, Lens, PartialEq)]
+    pub struct Placement {
+        pub show_grid: bool,
+        // moved, resized and new shapes land on the grid
+        pub snap: bool,
+        // a moved shape lines up with the shapes it comes near, showing a guide
+        pub guides: bool,
+    }
+
+    impl Default for Placement {
+        fn default() -> Self {
+            Placement {
+                show_grid: false,
+                snap: false,
+                guides: true,
+            }
+        }
+    }
+
+    impl Placement {
+        /// The nearest point on the grid when snapping, otherwise the point as it is
+        pub fn snap_point(&self, pos: Point) -> Point {
+            if self.snap {
+                Point::new((pos.x / GRID_SPACING).round() * GRID_SPACING, (pos.y / GRID_SPACING).round() * GRID_SPACING)
+            } else {
+                pos
+            }
+        }
+    }
+
+    /// Lines the selected shapes up on an edge or centre of the area around them,
+    /// or spaces their centres evenly from the first to the last
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum Arrange {
+        Left,
+        Centre,
+        Right,
+        Top,
+        Middle,
+        Bottom,
+        Across,
+        Down,
+    }
+
+    impl Arrange {
+        /// How many shapes need to be selected for it to do anything
+        pub fn needs(self) -> usize {
+            match self {
+                Arrange::Across | Arrange::Down => 3,
+                _ => 2,
+            }
+        }
+    }
+
+    /// This holds the data for the canvas.
+    /// This is created in AppData. use a lens on the Canvas widget from Appdata
+    /// much like lensing a string to a label
+    /// Shapes are only changed through the methods here, which keep the spatial index in step,
+    /// so there is no lens to them.
+    #[derive(Clone, Data)]
+    pub struct CanvasData {
+        shapes: Vector<Item>,
+        grid: SpatialGrid,
+        // the indices of the selected shapes, in drawing order
+        pub selected: OrdSet<usize>,
+        // the shapes when the resize overlay was opened, None while it is closed
+        resize_before: Option<Snapshot>,
+        // where the canvas is looking, not part of the action history
+        pub view: Viewport,
+        // what a click on an empty part of the canvas does
+        pub tool: Tool,
+        pub placement: Placement,
+    }
+
+    impl CanvasData {
+        pub fn new() -> Self {
+            CanvasData {
+                shapes: Vector::new(),
+                grid: SpatialGrid::new(),
+                selected: OrdSet::new(),
+                resize_before: None,
+                view: Viewport::default(),
+                tool: Tool::Circle,
+                placement: Placement::default(),
+            }
+        }
+
+        /// Read only access to every shape, for checking the index against a plain scan
+        #[cfg(test)]
+        pub fn shapes(&self) -> &Vector<Item> {
+            &self.shapes
+        }
+
+        pub fn add_shape(&mut self, item: Item) {
+            self.grid.insert(self.shapes.len(), item.paint_bounds());
+            self.shapes.push_back(item);
+        }
+
+        /// Changes a shape and moves it in the spatial index, returning false if nothing changed
+        pub fn update_shape(&mut self, index: usize, f: impl FnOnce(&mut dyn Shape)) -> bool {
+            self.update_item(index, |item| f(item.shape_mut()))
+        }
+
+        /// Changes a shape or its style, keeping the spatial index in step
+        fn update_item(&mut self, index: usize, f: impl FnOnce(&mut Item)) -> bool {
+            let old = &self.shapes[index];
+            let mut new = old.clone();
+            f(&mut new);
+            if new.same(old) {
+                return false;
+            }
+            self.grid.remove(index, old.paint_bounds());
+            self.grid.insert(index, new.paint_bounds());
+            self.shapes.set(index, new);
+            true
+        }
+
+        /// Indexes every shape again, after they have moved along in the vector
+        fn rebuild_grid(&mut self) {
+            self.grid = SpatialGrid::new();
+            for (i, item) in self.shapes.iter().enumerate() {
+                self.grid.insert(i, item.paint_bounds());
+            }
+        }
+
+        /// Sets each selected shape to its state in a snapshot with a change applied,
+        /// so a drag always works from where it started.
+        /// Returns the area that needs painting again, None if nothing changed.
+        pub fn transform_selection(&mut self, before: &Snapshot, f: impl Fn(usize, &mut dyn Shape)) -> Option<Rect> {
+            let mut changed: Option<Rect> = None;
+            for i in self.selected.clone() {
+                let mut item = before.shapes[i].clone();
+                f(i, item.shape_mut());
+                let old = self.shapes[i].paint_bounds();
+                if self.update_item(i, |current| *current = item) {
+                    let area = old.union(self.shapes[i].paint_bounds());
+                    changed = Some(changed.map_or(area, |c| c.union(area)));
+                }
+            }
+            changed
+        }
+
+        /// Scales every selected shape about its own centre so the shape dragged or on the
+        /// overlay ends up the given size
+        pub fn scale_selection(&mut self, before: &Snapshot, index: usize, size: f64) -> Option<Rect> {
+            let factor = size / before.shapes[index].shape().size();
+            self.transform_selection(before, |_, s| s.set_size(s.size() * factor))
+        }
+
+        /// The shape under a point, the one painted on top if they overlap
+        pub fn shape_at(&self, pos: Point) -> Option<usize> {
+            let mut top = None;
+            for &i in self.grid.at_point(pos) {
+                if self.shapes[i].shape().contains(pos) {
+                    top = top.max(Some(i));
+                }
+            }
+            top
+        }
+
+        /// The selected shape whose resize handle is near a point, the top one if there are several
+        pub fn handle_at(&self, pos: Point, reach: f64) -> Option<usize> {
+            self.selected.iter().rev()
+                .find(|&&i| (self.shapes[i].shape().handle() - pos).hypot() <= reach)
+                .copied()
+        }
+
+        /// The shape the overlay and the context menu go by when several are selected, the top one
+        pub fn primary(&self) -> Option<usize> {
+            self.selected.get_max().copied()
+        }
+
+        pub fn select_only(&mut self, index: usize) {
+            self.selected = OrdSet::unit(index);
+        }
+
+        /// Adds a shape to the selection, or takes it out if it is already in it
+        pub fn toggle_selected(&mut self, index: usize) {
+            if self.selected.remove(&index).is_none() {
+                self.selected.insert(index);
+            }
+        }
+
+        /// Selects the shapes entirely inside an area, along with those already selected if adding
+        pub fn select_in(&mut self, rect: Rect, already: &OrdSet<usize>) {
+            let inside = self.grid.in_rect(rect).into_iter().filter(|&i| {
+                let bounds = self.shapes[i].shape().bounds();
+                rect.union(bounds) == rect
+            });
+            self.selected = already.clone().union(inside.collect());
+        }
+
+        /// The area around the selected shapes
+        pub fn selection_bounds(&self) -> Option<Rect> {
+            self.selected.iter().map(|&i| self.shapes[i].shape().bounds()).reduce(|a, b| a.union(b))
+        }
+
+        /// What is selected, for the resize overlay
+        pub fn selection_name(&self) -> Option<String> {
+            match self.selected.len() {
+                0 => None,
+                1 => self.primary().map(|i| format!("{} {}", self.shapes[i].shape().name(), i)),
+                n => Some(format!("{} shapes", n)),
+            }
+        }
+
+        /// The style of the main selected shape, for the context menu
+        pub fn selected_style(&self) -> Option<&Style> {
+            self.primary().map(|i| &self.shapes[i].style)
+        }
+
+        /// Changes the style of the selected shapes, returning the action to log if any changed
+        pub fn restyle(&mut self, change: &StyleChange) -> Option<ActionItem> {
+            let before = self.snapshot();
+            let mut changed = false;
+            for i in self.selected.clone() {
+                changed |= self.update_item(i, |item| item.style.apply(change));
+            }
+            if changed {
+                Some(ActionItem::new(ActionType::Restyle, before, self.snapshot()))
+            } else {
+                None
+            }
+        }
+
+        /// Moves the selected shapes to the top of the drawing or the bottom, keeping their order
+        /// among themselves. They stay selected. Returns the action to log if the order changed.
+        pub fn reorder(&mut self, to_front: bool) -> Option<ActionItem> {
+            let count = self.selected.len();
+            let total = self.shapes.len();
+            let moved: OrdSet<usize> = if to_front { (total - count..total).collect() } else { (0..count).collect() };
+            if count == 0 || moved == self.selected {
+                return None;
+            }
+            let before = self.snapshot();
+            let (mut chosen, mut rest) = (Vector::new(), Vector::new());
+            for (i, item) in self.shapes.iter().enumerate() {
+                if self.selected.contains(&i) {
+                    chosen.push_back(item.clone());
+                } else {
+                    rest.push_back(item.clone());
+                }
+            }
+            self.shapes = if to_front {
+                rest.append(chosen);
+                rest
+            } else {
+                chosen.append(rest);
+                chosen
+            };
+            self.selected = moved;
+            self.rebuild_grid();
+            Some(ActionItem::new(ActionType::Reorder, before, self.snapshot()))
+        }
+
+        /// Selects the shape drawn after the main selected one, or before it going backwards,
+        /// wrapping round at the ends
+        pub fn select_next(&mut self, backwards: bool) {
+            let count = self.shapes.len();
+            if count == 0 {
+                return;
+            }
+            let index = match (self.primary(), backwards) {
+                (Some(i), false) => (i + 1) % count,
+                (Some(i), true) => (i + count - 1) % count,
+                (None, false) => 0,
+                (None, true) => count - 1,
+            };
+            self.select_only(index);
+        }
+
+        /// Moves the selected shapes, returning the action to log if there were any
+        pub fn nudge_selection(&mut self, delta: Vec2) -> Option<ActionItem> {
+            let before = self.snapshot();
+            self.transform_selection(&before, |_, shape| shape.translate(delta))?;
+            Some(ActionItem::new(ActionType::Move, before, self.snapshot()))
+        }
+
+        /// Changes the size of the main selected shape by a step, within the range of the slider,
+        /// and scales the others along with it. Returns the action to log if anything changed
+        pub fn resize_selection_by(&mut self, step: f64) -> Option<ActionItem> {
+            let index = self.primary()?;
+            let before = self.snapshot();
+            let size = (self.shapes[index].shape().size() + step).clamp(MIN_RADIUS, MAX_RADIUS);
+            self.scale_selection(&before, index, size)?;
+            Some(ActionItem::new(ActionType::Adjustment, before, self.snapshot()))
+        }
+
+        /// How far to move the selected shapes for a drag of the given distance, so the dragged
+        /// shape's centre lands on the grid when snapping, and the shapes line up with any
+        /// other shape in an area that they come within reach of.
+        /// Returns the distance and the guides to show for the shapes they line up with.
+        pub fn place_selection(&self, before: &Snapshot, index: usize, delta: Vec2, area: Rect, reach: f64)
+            -> (Vec2, Vec<kurbo::Line>) {
+            let anchor = before.shapes[index].shape().bounds().center() + delta;
+            let mut placed = delta + (self.placement.snap_point(anchor) - anchor);
+            if !self.placement.guides {
+                return (placed, Vec::new());
+            }
+
+            let moving = match self.selected.iter().map(|&i| before.shapes[i].shape().bounds()).reduce(|a, b| a.union(b)) {
+                Some(bounds) => bounds + delta,
+                None => return (placed, Vec::new()),
+            };
+            let others: Vec<Rect> = self.shapes_in(area).into_iter()
+                .filter(|i| !self.selected.contains(i))
+                .map(|i| self.shapes[i].shape().bounds())
+                .collect();
+            // the closest edge or centre of another shape within reach along one axis,
+            // centres come first so they win a tie
+            let nearest = |lines: [f64; 3], targets: &dyn Fn(&Rect) -> [f64; 3]| {
+                let mut best: Option<(f64, f64)> = None;
+                for other in &others {
+                    for target in targets(other).iter() {
+                        for line in lines.iter() {
+                            let shift = target - line;
+                            if shift.abs() <= reach && best.is_none_or(|(b, _)| shift.abs() < b.abs()) {
+                                best = Some((shift, *target));
+                            }
+                        }
+                    }
+                }
+                best
+            };
+            let across = nearest([moving.center().x, moving.x0, moving.x1], &|r| [r.center().x, r.x0, r.x1]);
+            let down = nearest([moving.center().y, moving.y0, moving.y1], &|r| [r.center().y, r.y0, r.y1]);
+            if let Some((shift, _)) = across {
+                placed.x = delta.x + shift;
+            }
+            if let Some((shift, _)) = down {
+                placed.y = delta.y + shift;
+            }
+
+            // each guide runs the length of the shapes lined up on it
+            let moved = moving + (placed - delta);
+            let mut guides = Vec::new();
+            if let Some((_, x)) = across {
+                let (y0, y1) = others.iter()
+                    .filter(|r| [r.x0, r.center().x, r.x1].iter().any(|&t| (t - x).abs() < 1e-9))
+                    .fold((moved.y0, moved.y1), |(y0, y1), r| (y0.min(r.y0), y1.max(r.y1)));
+                guides.push(kurbo::Line::new((x, y0), (x, y1)));
+            }
+            if let Some((_, y)) = down {
+                let (x0, x1) = others.iter()
+                    .filter(|r| [r.y0, r.center().y, r.y1].iter().any(|&t| (t - y).abs() < 1e-9))
+                    .fold((moved.x0, moved.x1), |(x0, x1), r| (x0.min(r.x0), x1.max(r.x1)));
+                guides.push(kurbo::Line::new((x0, y), (x1, y)));
+            }
+            (placed, guides)
+        }
+
+        /// Aligns or distributes the selected shapes, returning the action to log if any moved
+        pub fn arrange(&mut self, how: Arrange) -> Option<ActionItem> {
+            if self.selected.len() < how.needs() {
+                return None;
+            }
+            let area = self.selection_bounds()?;
+            let before = self.snapshot();
+            let bounds = |i: usize| before.shapes[i].shape().bounds();
+            let moves: Vec<(usize, Vec2)> = match how {
+                Arrange::Across | Arrange::Down => {
+                    let across = how == Arrange::Across;
+                    let centre = |i: usize| if across { bounds(i).center().x } else { bounds(i).center().y };
+                    let mut order: Vec<usize> = self.selected.iter().copied().collect();
+                    order.sort_by(|&a, &b| centre(a).total_cmp(&centre(b)));
+                    let first = centre(order[0]);
+                    let step = (centre(order[order.len() - 1]) - first) / (order.len() - 1) as f64;
+                    order.iter().enumerate().map(|(n, &i)| {
+                        let shift = first + step * n as f64 - centre(i);
+                        (i, if across { Vec2::new(shift, 0.) } else { Vec2::new(0., shift) })
+                    }).collect()
+                },
+                _ => self.selected.iter().map(|&i| {
+                    let b = bounds(i);
+                    let shift = match how {
+                        Arrange::Left => Vec2::new(area.x0 - b.x0, 0.),
+                        Arrange::Centre => Vec2::new(area.center().x - b.center().x, 0.),
+                        Arrange::Right => Vec2::new(area.x1 - b.x1, 0.),
+                        Arrange::Top => Vec2::new(0., area.y0 - b.y0),
+                        Arrange::Middle => Vec2::new(0., area.center().y - b.center().y),
+                        _ => Vec2::new(0., area.y1 - b.y1),
+                    };
+                    (i, shift)
+                }).collect(),
+            };
+            let mut changed = false;
+            for (i, shift) in moves {
+                changed |= self.update_shape(i, |shape| shape.translate(shift));
+            }
+            if changed {
+                Some(ActionItem::new(ActionType::Arrange, before, self.snapshot()))
+            } else {
+                None
+            }
+        }
+
+        /// Removes the selected shapes, returning the action to log if there were any
+        pub fn delete_selection(&mut self) -> Option<ActionItem> {
+            if self.selected.is_empty() {
+                return None;
+            }
+            let before = self.snapshot();
+            self.shapes = self.shapes.iter().enumerate()
+                .filter(|(i, _)| !self.selected.contains(i))
+                .map(|(_, item)| item.clone())
+                .collect();
+            self.selected.clear();
+            self.rebuild_grid();
+            Some(ActionItem::new(ActionType::Deletion, before, self.snapshot()))
+        }
+
+        /// Copies the selected shapes a little down and to the right, on top of the drawing.
+        /// The copies are selected, returning the action to log if there were any
+        pub fn duplicate_selection(&mut self) -> Option<ActionItem> {
+            if self.selected.is_empty() {
+                return None;
+            }
+            let before = self.snapshot();
+            let first = self.shapes.len();
+            for i in self.selected.clone() {
+                let mut item = self.shapes[i].clone();
+                item.shape_mut().translate(Vec2::new(DUPLICATE_OFFSET, DUPLICATE_OFFSET));
+                self.add_shape(item);
+            }
+            self.selected = (first..self.shapes.len()).collect();
+            Some(ActionItem::new(ActionType::Duplication, before, self.snapshot()))
+        }
+
+        /// The selected shapes in the format of to_tsv, for copying. None when nothing is selected
+        pub fn selection_tsv(&self) -> Option<String> {
+            if self.selected.is_empty() {
+                return None;
+            }
+            Some(self.selected.iter().map(|&i| self.shapes[i].to_tsv() + "\n").collect())
+        }
+
+        /// The selected shapes as an SVG document fitted around them, for pasting into other apps
+        pub fn selection_svg(&self) -> Option<String> {
+            let bounds = self.selected.iter()
+                .map(|&i| self.shapes[i].paint_bounds())
+                .reduce(|a, b| a.union(b))?;
+            let mut svg = format!(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\" width=\"{}\" height=\"{}\">\n",
+                bounds.x0, bounds.y0, bounds.width(), bounds.height(), bounds.width(), bounds.height(),
+            );
+            for &i in &self.selected {
+                svg.push_str(&format!("  {}\n", self.shapes[i].to_svg()));
+            }
+            svg.push_str("</svg>\n");
+            Some(svg)
+        }
+
+        /// Adds the shapes in copied text on top of the drawing and selects them.
+        /// When the copies would land exactly on shapes already there, as when pasting twice,
+        /// they are moved down and to the right until they don't.
+        /// Returns the action to log, None if the text holds no shapes.
+        pub fn paste(&mut self, text: &str) -> Option<ActionItem> {
+            let mut items: Vec<Item> = text.lines().filter_map(Item::from_tsv).collect();
+            let first = items.first()?.clone();
+            let offset = Vec2::new(DUPLICATE_OFFSET, DUPLICATE_OFFSET);
+            let mut shift = Vec2::ZERO;
+            loop {
+                let mut moved = first.clone();
+                moved.shape_mut().translate(shift);
+                let covered = self.shapes_in(moved.shape().bounds()).into_iter()
+                    .any(|i| self.shapes[i].same(&moved));
+                if !covered {
+                    break;
+                }
+                shift += offset;
+            }
+
+            let before = self.snapshot();
+            let start = self.shapes.len();
+            for item in &mut items {
+                item.shape_mut().translate(shift);
+            }
+            for item in items {
+                self.add_shape(item);
+            }
+            self.selected = (start..self.shapes.len()).collect();
+            Some(ActionItem::new(ActionType::Paste, before, self.snapshot()))
+        }
+
+        /// Swaps the drawing for the shapes in a saved file, or for nothing to start again.
+        /// The view and the tools stay as they are. Returns the action to log if anything changed
+        pub fn replace_drawing(&mut self, text: &str) -> Option<ActionItem> {
+            let loaded = CanvasData::from_tsv(text);
+            if loaded.shapes.is_empty() && self.shapes.is_empty() {
+                return None;
+            }
+            let before = self.snapshot();
+            self.shapes = loaded.shapes;
+            self.grid = loaded.grid;
+            self.selected.clear();
+            Some(ActionItem::new(ActionType::Replace, before, self.snapshot()))
+        }
+
+        /// The shapes that may be visible in an area, in drawing order
+        pub fn shapes_in(&self, rect: Rect) -> Vec<usize> {
+            self.grid.in_rect(rect)
+        }
+
+        /// The area covered by every shape, None when there are none
+        pub fn content_bounds(&self) -> Option<Rect> {
+            self.grid.bounds()
+        }
+
+        /// Zooms and pans so every shape fits on a canvas of the given size
+        pub fn fit_all(&mut self, size: Size) {
+            self.view = match self.content_bounds() {
+                Some(bounds) => {
+                    let bounds = bounds.inflate(PADDING, PADDING);
+                    let zoom = (size.width / bounds.width())
+                        .min(size.height / bounds.height())
+                        .clamp(MIN_ZOOM, MAX_ZOOM);
+                    Viewport {
+                        origin: bounds.center() - size.to_vec2() / (2. * zoom),
+                        zoom,
+                    }
+                },
+                None => Viewport::default(),
+            }
+        }
+
+        pub fn snapshot(&self) -> Snapshot {
+            Snapshot {
+                shapes: self.shapes.clone(),
+                grid: self.grid.clone(),
+            }
+        }
+
+        /// Puts back shapes from the action log, dropping any selection that is gone
+        pub fn restore(&mut self, snapshot: Snapshot) {
+            self.shapes = snapshot.shapes;
+            self.grid = snapshot.grid;
+            let count = self.shapes.len();
+            if self.selected.iter().any(|&i| i >= count) {
+                self.selected = self.selected.iter().filter(|&&i| i < count).copied().collect();
+            }
+        }
+
+        /// Opens the resize overlay for the selected shapes
+        pub fn open_resize(&mut self) {
+            if !self.selected.is_empty() && self.resize_before.is_none() {
+                self.resize_before = Some(self.snapshot());
+            }
+        }
+
+        pub fn is_resizing(&self) -> bool {
+            self.resize_before.is_some()
+        }
+
+        /// Closes the resize overlay, returning the action to log if the size was changed
+        pub fn close_resize(&mut self) -> Option<ActionItem> {
+            let before = self.resize_before.take()?;
+            if before.shapes.same(&self.shapes) {
+                None
+            } else {
+                Some(ActionItem::new(ActionType::Adjustment, before, self.snapshot()))
+            }
+        }
+
+        /// One shape per line, as written by Item::to_tsv
+        pub fn to_tsv(&self) -> String {
+            self.shapes.iter().map(|s| s.to_tsv() + "\n").collect()
+        }
+
+        /// Reads the format written by to_tsv, lines that can't be read are skipped
+        pub fn from_tsv(text: &str) -> CanvasData {
+            let mut data = CanvasData::new();
+            for item in text.lines().filter_map(Item::from_tsv) {
+                data.add_shape(item);
+            }
+            data
+        }
+    }
+
+    /// Lens to the size of the main selected shape, for the slider on the resize overlay.
+    /// The other selected shapes are scaled along with it.
+    pub struct SelectedSize;
+
+    impl Lens<CanvasData, f64> for SelectedSize {
+        fn with<V, F: FnOnce(&f64) -> V>(&self, data: &CanvasData, f: F) -> V {
+            f(&data.primary().map_or(MIN_RADIUS, |i| data.shapes[i].shape().size()))
+        }
+
+        fn with_mut<V, F: FnOnce(&mut f64) -> V>(&self, data: &mut CanvasData, f: F) -> V {
+            let mut size = data.primary().map_or(MIN_RADIUS, |i| data.shapes[i].shape().size());
+            let value = f(&mut size);
+            if let Some(i) = data.primary() {
+                // only written back when changed, rounding in the scale would change the shapes
+                if data.shapes[i].shape().size() != size {
+                    // scaled from when the overlay opened, so shapes held at a size limit catch up
+                    let before = data.resize_before.clone().unwrap_or_else(|| data.snapshot());
+                    data.scale_selection(&before, i, size);
+                }
+            }
+            value
+        }
+    }
+
+    /// What a drag on the canvas is doing
+    #[derive(Clone, Copy, PartialEq)]
+    enum DragKind {
+        /// Moves the selected shapes along with the mouse from the document point grabbed
+        Move { grab: Point },
+        /// Moves the resize handle, the other selected shapes are scaled along with it
+        Resize,
+        /// Sizes a shape that was just drawn, it is logged as one creation
+        Create,
+    }
+
+    /// A drag in progress, from mouse down to mouse up
+    struct Drag {
+        kind: DragKind,
+        index: usize,
+        // in screen coordinates, to tell a click from a drag
+        start: Point,
+        // the shapes before the drag, to record a single action once it ends
+        before: Snapshot,
+        moved: bool,
+        // clicking a shape that was already selected, without dragging it, deselects it
+        // or, from a group, selects just that shape
+        was_selected: bool,
+    }
+
+    /// A rubber band being dragged out to select the shapes inside it
+    struct Band {
+        // the corners in document coordinates
+        start: Point,
+        end: Point,
+        // the selection before the band, kept when shift was held
+        already: OrdSet<usize>,
+    }
+
+    /// The direction a scrollbar moves the view
+    #[derive(Clone, Copy, PartialEq)]
+    enum Axis {
+        Horizontal,
+        Vertical,
+    }
+
+    /// The canvas widget requires a lens to CanvasData
+    /// For example:
+    /// ```no_run
+    /// struct AppData{
+    ///     canvas: custom::CanvasData,
+    /// }
+    ///
+    /// let canvas = custom::Canvas::new().lens(AppData::canvas);
+    /// ```
+    /// The wheel zooms around the cursor, shift and the wheel or a drag with the middle button pans.
+    /// With the polygon tool each click adds a point, clicking the first point again or Enter
+    /// finishes the polygon and Escape drops it.
+    /// Dragging over an empty part of the canvas with the select tool, or with shift held, selects
+    /// the shapes inside. Shift and a click adds a shape to the selection or takes it out.
+    ///
+    /// Everything can be done from the keyboard once the canvas has focus, which it takes when the
+    /// window opens and on a click:
+    /// Tab and shift-Tab step through the shapes, the arrows move the selected shapes, or the
+    /// keyboard cursor when nothing is selected, + and - change their size and Enter opens the
+    /// resize overlay. Space draws with the tool at the keyboard cursor.
+    pub struct Canvas {
+        drag: Option<Drag>,
+        band: Option<Band>,
+        // where Space draws, in document coordinates. Hidden until the arrows move it
+        cursor: Option<Point>,
+        // the lines shown while a moved shape is lined up with others, in document coordinates
+        guides: Vec<kurbo::Line>,
+        // the points of a polygon being drawn, in document coordinates
+        polygon: Vector<Point>,
+        // the last mouse position of a middle button pan
+        pan: Option<Point>,
+        // the scrollbar being dragged and the last mouse position
+        scroll: Option<(Axis, Point)>,
+        // the area covered by the shapes, kept here so it is only worked out when they change
+        content: Option<Rect>,
+        overlay: WidgetPod<CanvasData, Box<dyn Widget<CanvasData>>>,
+        // where the overlay was placed when it opened, it stays there as the shape changes size
+        overlay_origin: Option<Point>,
+        // set when the event already asked for just the changed area to be painted,
+        // so update does not repaint everything
+        invalidated: bool,
+    }
+
+    impl Canvas {
+        pub fn new() -> Self {
+            Canvas {
+                drag: None,
+                band: None,
+                cursor: None,
+                guides: Vec::new(),
+                polygon: Vector::new(),
+                pan: None,
+                scroll: None,
+                content: None,
+                overlay: WidgetPod::new(Box::new(build_overlay())),
+                overlay_origin: None,
+                invalidated: false,
+            }
+        }
+
+        /// Beside the selected shapes, on the right unless it would go off the canvas
+        fn place_overlay(data: &CanvasData, overlay: Size, canvas: Size) -> Point {
+            let bounds = match data.selection_bounds() {
+                Some(bounds) => data.view.screen_rect(bounds),
+                None => return Point::ORIGIN,
+            };
+            let right = bounds.x1 + PADDING;
+            let x = if right + overlay.width <= canvas.width {
+                right
+            } else {
+                bounds.x0 - PADDING - overlay.width
+            };
+            let y = bounds.center().y - overlay.height / 2.;
+            Point::new(
+                x.min(canvas.width - overlay.width).max(0.),
+                y.min(canvas.height - overlay.height).max(0.),
+            )
+        }
+
+        /// Closes the overlay, asking for the change to be logged
+        fn dismiss_overlay(ctx: &mut EventCtx, data: &mut CanvasData) {
+            if let Some(item) = data.close_resize() {
+                ctx.submit_command(RECORD_ACTION.with(item), None);
+            }
+            ctx.request_layout();
+        }
+
+        fn start_drag(&mut self, kind: DragKind, index: usize, start: Point, was_selected: bool, before: Snapshot) {
+            self.drag = Some(Drag {
+                kind,
+                index,
+                start,
+                before,
+                moved: false,
+                was_selected,
+            });
+        }
+
+        /// The part of the document the scrollbars cover, the shapes and whatever is in view
+        fn scroll_range(&self, visible: Rect) -> Rect {
+            self.content.map_or(visible, |content| content.union(visible))
+        }
+
+        /// The track and thumb of a scrollbar in screen coordinates, None when everything is in view
+        fn scrollbar(&self, axis: Axis, data: &CanvasData, size: Size, env: &Env) -> Option<(Rect, Rect)> {
+            let width = env.get(druid::theme::SCROLLBAR_WIDTH);
+            let pad = env.get(druid::theme::SCROLLBAR_PAD);
+            let visible = data.view.doc_rect(size.to_rect());
+            let range = self.scroll_range(visible);
+            // the tracks stop short of the corner where they would meet
+            let end = width + pad * 2.;
+            let (track, thumb) = match axis {
+                Axis::Horizontal => {
+                    if range.width() <= visible.width() + 0.5 {
+                        return None;
+                    }
+                    let track = Rect::new(pad, size.height - width - pad, size.width - end, size.height - pad);
+                    let scale = track.width() / range.width();
+                    let x0 = track.x0 + (visible.x0 - range.x0) * scale;
+                    (track, Rect::new(x0, track.y0, x0 + visible.width() * scale, track.y1))
+                },
+                Axis::Vertical => {
+                    if range.height() <= visible.height() + 0.5 {
+                        return None;
+                    }
+                    let track = Rect::new(size.width - width - pad, pad, size.width - pad, size.height - end);
+                    let scale = track.height() / range.height();
+                    let y0 = track.y0 + (visible.y0 - range.y0) * scale;
+                    (track, Rect::new(track.x0, y0, track.x1, y0 + visible.height() * scale))
+                },
+            };
+            Some((track, thumb))
+        }
+
+        /// Pans the view as the thumb of a scrollbar is dragged by the given distance
+        fn scroll_by(&self, axis: Axis, delta: Vec2, data: &mut CanvasData, size: Size, env: &Env) {
+            if let Some((track, _)) = self.scrollbar(axis, data, size, env) {
+                let range = self.scroll_range(data.view.doc_rect(size.to_rect()));
+                // the thumb moves the opposite way to a pan of the canvas
+                let delta = match axis {
+                    Axis::Horizontal => Vec2::new(-delta.x * range.width() / track.width(), 0.),
+                    Axis::Vertical => Vec2::new(0., -delta.y * range.height() / track.height()),
+                };
+                data.view.pan(delta * data.view.zoom);
+            }
+        }
+
+        /// Starts dragging a scrollbar if the mouse is on one.
+        /// A click on the track jumps the thumb there first.
+        fn press_scrollbar(&mut self, pos: Point, data: &mut CanvasData, size: Size, env: &Env) -> bool {
+            for &axis in &[Axis::Horizontal, Axis::Vertical] {
+                if let Some((track, thumb)) = self.scrollbar(axis, data, size, env) {
+                    if track.contains(pos) {
+                        if !thumb.contains(pos) {
+                            self.scroll_by(axis, pos - thumb.center(), data, size, env);
+                        }
+                        self.scroll = Some((axis, pos));
+                        return true;
+                    }
+                }
+            }
+            false
+        }
+
+        /// The keyboard controls, changes to the shapes go to the delegate to be logged
+        fn key_down(&mut self, ctx: &mut EventCtx, e: &KeyEvent, data: &mut CanvasData) {
+            let far = if e.mods.shift { 10. } else { 1. };
+            let direction = match e.key_code {
+                KeyCode::ArrowLeft => Some(Vec2::new(-1., 0.)),
+                KeyCode::ArrowRight => Some(Vec2::new(1., 0.)),
+                KeyCode::ArrowUp => Some(Vec2::new(0., -1.)),
+                KeyCode::ArrowDown => Some(Vec2::new(0., 1.)),
+                _ => None,
+            };
+            let size_step = match e.key_code {
+                KeyCode::Equals | KeyCode::NumpadAdd => Some(SIZE_STEP * far),
+                KeyCode::Minus | KeyCode::NumpadSubtract => Some(-SIZE_STEP * far),
+                _ => None,
+            };
+
+            if e.key_code == KeyCode::Delete || e.key_code == KeyCode::Backspace {
+                ctx.submit_command(CVS_CTX_DELETE, None);
+            } else if e.key_code == KeyCode::Tab {
+                data.select_next(e.mods.shift);
+                if let Some(bounds) = data.selection_bounds() {
+                    data.view.reveal(bounds, ctx.size());
+                }
+            } else if let Some(direction) = direction {
+                if data.selected.is_empty() {
+                    let step = direction * CURSOR_STEP * far / data.view.zoom;
+                    let cursor = self.cursor.unwrap_or_else(|| data.view.doc_rect(ctx.size().to_rect()).center()) + step;
+                    self.cursor = Some(cursor);
+                    let reach = CURSOR_SIZE / data.view.zoom;
+                    data.view.reveal(Rect::from_center_size(cursor, (reach * 2., reach * 2.)), ctx.size());
+                    ctx.request_paint();
+                } else if let Some(item) = data.nudge_selection(direction * NUDGE * far) {
+                    if let Some(bounds) = data.selection_bounds() {
+                        data.view.reveal(bounds, ctx.size());
+                    }
+                    ctx.submit_command(RECORD_ACTION.with(item), None);
+                }
+            } else if let Some(step) = size_step {
+                if let Some(item) = data.resize_selection_by(step) {
+                    ctx.submit_command(RECORD_ACTION.with(item), None);
+                }
+            } else if e.key_code == KeyCode::Space {
+                let cursor = *self.cursor.get_or_insert_with(|| data.view.doc_rect(ctx.size().to_rect()).center());
+                self.draw_at(ctx, cursor, data);
+            } else if e.key_code == KeyCode::Return || e.key_code == KeyCode::NumpadEnter {
+                if self.polygon.is_empty() {
+                    data.open_resize();
+                } else {
+                    self.finish_polygon(ctx, data);
+                }
+            } else if e.key_code == KeyCode::Escape {
+                // the polygon being drawn goes first, then the selection and the keyboard cursor
+                if self.polygon.is_empty() {
+                    data.selected.clear();
+                    self.cursor = None;
+                } else {
+                    self.polygon.clear();
+                }
+                ctx.request_paint();
+            } else {
+                return;
+            }
+            ctx.set_handled();
+        }
+
+        /// What Space does at the keyboard cursor: adds a point to a polygon, picks out the shape
+        /// there with the select tool, or draws a shape of the default size
+        fn draw_at(&mut self, ctx: &mut EventCtx, pos: Point, data: &mut CanvasData) {
+            let pos = data.placement.snap_point(pos);
+            match data.tool {
+                Tool::Polygon => {
+                    self.polygon.push_back(pos);
+                    ctx.request_paint();
+                },
+                Tool::Select => {
+                    if let Some(index) = data.shape_at(pos) {
+                        data.toggle_selected(index);
+                    }
+                },
+                tool => {
+                    if let Some(item) = Item::new(tool, pos) {
+                        let before = data.snapshot();
+                        data.selected.clear();
+                        data.add_shape(item);
+                        let item = ActionItem::new(ActionType::Creation, before, data.snapshot());
+                        ctx.submit_command(RECORD_ACTION.with(item), None);
+                    }
+                },
+            }
+        }
+
+        /// Adds the polygon being drawn to the canvas, if it has enough points
+        fn finish_polygon(&mut self, ctx: &mut EventCtx, data: &mut CanvasData) {
+            let points = std::mem::take(&mut self.polygon);
+            if let Some(item) = Item::polygon(points) {
+                let before = data.snapshot();
+                data.selected.clear();
+                data.add_shape(item);
+                let item = ActionItem::new(ActionType::Creation, before, data.snapshot());
+                ctx.submit_command(RECORD_ACTION.with(item), None);
+            }
+            ctx.request_paint();
+        }
+
+        /// The points placed so far and the lines between them, the same size at any zoom
+        fn paint_polygon(ctx: &mut PaintCtx, points: &Vector<Point>, zoom: f64) {
+            let mut path = BezPath::new();
+            for (i, &p) in points.iter().enumerate() {
+                if i == 0 {
+                    path.move_to(p);
+                } else {
+                    path.line_to(p);
+                }
+                let size = HANDLE_SIZE * 2. / zoom;
+                ctx.fill(Rect::from_center_size(p, (size, size)), &Color::WHITE);
+            }
+            ctx.stroke(path, &Color::BLACK, 1. / zoom);
+        }
+
+        /// The lines of the grid across an area, spread further apart when they would crowd together
+        fn paint_grid(ctx: &mut PaintCtx, area: Rect, zoom: f64) {
+            let mut spacing = GRID_SPACING;
+            while spacing * zoom < GRID_MIN_GAP {
+                spacing *= 5.;
+            }
+            let mut path = BezPath::new();
+            let mut x = (area.x0 / spacing).floor() * spacing;
+            while x <= area.x1 {
+                path.move_to((x, area.y0));
+                path.line_to((x, area.y1));
+                x += spacing;
+            }
+            let mut y = (area.y0 / spacing).floor() * spacing;
+            while y <= area.y1 {
+                path.move_to((area.x0, y));
+                path.line_to((area.x1, y));
+                y += spacing;
+            }
+            ctx.stroke(path, &Color::grey(0.5), 1. / zoom);
+        }
+
+        fn paint_scrollbars(&self, ctx: &mut PaintCtx, data: &CanvasData, env: &Env) {
+            let radius = env.get(druid::theme::SCROLLBAR_RADIUS);
+            let color = env.get(druid::theme::SCROLLBAR_COLOR).with_alpha(env.get(druid::theme::SCROLLBAR_MAX_OPACITY));
+            let border = env.get(druid::theme::SCROLLBAR_BORDER_COLOR);
+            let edge = env.get(druid::theme::SCROLLBAR_EDGE_WIDTH);
+            for &axis in &[Axis::Horizontal, Axis::Vertical] {
+                if let Some((_, thumb)) = self.scrollbar(axis, data, ctx.size(), env) {
+                    let thumb = thumb.to_rounded_rect(radius);
+                    ctx.fill(thumb, &color);
+                    ctx.stroke(thumb, &border, edge);
+                }
+            }
+        }
+    }
+
+    impl Widget<CanvasData> for Canvas {
+        fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut CanvasData, env: &Env) {
+            // the keyboard works as soon as the window opens, and again after a click elsewhere
+            match event {
+                Event::WindowConnected => ctx.request_focus(),
+                Event::MouseDown(_) if !ctx.is_focused() => ctx.request_focus(),
+                _ => (),
+            }
+
+            if let Event::Command(cmd) = event {
+                if cmd.is(CVS_FIT_ALL) {
+                    data.fit_all(ctx.size());
+                    // the overlay follows the shapes it is resizing
+                    self.overlay_origin = None;
+                    ctx.request_layout();
+                    ctx.set_handled();
+                    return;
+                }
+            }
+
+            // the overlay is modal, while it is open a click outside it or a key only closes it
+            if data.is_resizing() {
+                self.overlay.event(ctx, event, data, env);
+                match event {
+                    Event::MouseDown(e) if !self.overlay.layout_rect().contains(e.pos) => {
+                        Canvas::dismiss_overlay(ctx, data);
+                    },
+                    Event::KeyDown(e) if e.key_code == KeyCode::Escape || e.key_code == KeyCode::Return => {
+                        Canvas::dismiss_overlay(ctx, data);
+                    },
+                    // + and - move the slider, the change is logged when the overlay closes
+                    Event::KeyDown(e) => {
+                        let step = if e.mods.shift { SIZE_STEP * 10. } else { SIZE_STEP };
+                        let step = match e.key_code {
+                            KeyCode::Equals | KeyCode::NumpadAdd => step,
+                            KeyCode::Minus | KeyCode::NumpadSubtract => -step,
+                            _ => return,
+                        };
+                        SelectedSize.with_mut(data, |size| *size = (*size + step).clamp(MIN_RADIUS, MAX_RADIUS));
+                        ctx.set_handled();
+                    },
+                    _ => (),
+                }
+                return;
+            }
+
+            match event {
+                Event::MouseDown(e) => {
+                    // println!("{:?} pressed at {}", e.button , e.pos);
+                    let pos = data.view.to_doc(e.pos);
+                    if self.cursor.take().is_some() {
+                        ctx.request_paint();
+                    }
+
+                    match e.button {
+                        MouseButton::Left => {
+                            // the handle of a selected shape takes priority over any shape below it
+                            let handle = data.handle_at(pos, HANDLE_SIZE * 2. / data.view.zoom);
+
+                            if self.press_scrollbar(e.pos, data, ctx.size(), env) {
+                                ctx.set_active(true);
+                            } else if !self.polygon.is_empty() || (data.tool == Tool::Polygon && handle.is_none() && data.shape_at(pos).is_none()) {
+                                // the first point again finishes the polygon
+                                let closes = self.polygon.front()
+                                    .is_some_and(|&first| (first - pos).hypot() * data.view.zoom <= HANDLE_SIZE * 2.);
+                                if closes {
+                                    self.finish_polygon(ctx, data);
+                                } else {
+                                    self.polygon.push_back(data.placement.snap_point(pos));
+                                }
+                            } else if let Some(index) = handle {
+                                self.start_drag(DragKind::Resize, index, e.pos, true, data.snapshot());
+                                ctx.set_active(true);
+                            } else if let Some(index) = data.shape_at(pos) {
+                                let was_selected = data.selected.contains(&index);
+                                if e.mods.shift {
+                                    data.toggle_selected(index);
+                                } else {
+                                    if !was_selected {
+                                        data.select_only(index);
+                                    }
+                                    self.start_drag(DragKind::Move { grab: pos }, index, e.pos, was_selected, data.snapshot());
+                                    ctx.set_active(true);
+                                }
+                            } else if data.tool == Tool::Select || e.mods.shift {
+                                let already = if e.mods.shift { data.selected.clone() } else { OrdSet::new() };
+                                data.selected = already.clone();
+                                self.band = Some(Band { start: pos, end: pos, already });
+                                ctx.set_active(true);
+                            } else if let Some(item) = Item::new(data.tool, data.placement.snap_point(pos)) {
+                                let before = data.snapshot();
+                                data.selected.clear();
+                                data.add_shape(item);
+                                let index = data.shapes.len() - 1;
+                                self.start_drag(DragKind::Create, index, e.pos, false, before);
+                                ctx.set_active(true);
+                            }
+                            ctx.request_paint()
+                        },
+
+                        MouseButton::Middle => {
+                            self.pan = Some(e.pos);
+                            ctx.set_active(true);
+                        },
+
+                        MouseButton::Right => {
+                            // the menu is for the shape under the mouse, or the group it is in
+                            if let Some(index) = data.shape_at(pos).filter(|i| !data.selected.contains(i)) {
+                                data.select_only(index);
+                            }
+                            let menu = ContextMenu::new(
+                                build_context(data)
+                                , e.pos
+                            );
+                            ctx.show_context_menu(menu);
+                        },
+                        _ => ()
+                    }
+                },
+                Event::MouseMove(e) => {
+                    if let Some(last) = self.pan {
+                        data.view.pan(e.pos - last);
+                        self.pan = Some(e.pos);
+                    } else if let Some((axis, last)) = self.scroll {
+                        self.scroll_by(axis, e.pos - last, data, ctx.size(), env);
+                        self.scroll = Some((axis, e.pos));
+                    } else if let Some(band) = &mut self.band {
+                        band.end = data.view.to_doc(e.pos);
+                        data.select_in(Rect::from_points(band.start, band.end), &band.already);
+                        ctx.request_paint();
+                    } else if let Some(drag) = &mut self.drag {
+                        if !drag.moved && (e.pos - drag.start).hypot() < DRAG_THRESHOLD {
+                            return;
+                        }
+                        drag.moved = true;
+                        let pos = data.view.to_doc(e.pos);
+                        let index = drag.index;
+                        let changed = match drag.kind {
+                            DragKind::Move { grab } => {
+                                let area = data.view.doc_rect(ctx.size().to_rect());
+                                let reach = GUIDE_REACH / data.view.zoom;
+                                let (delta, guides) = data.place_selection(&drag.before, index, pos - grab, area, reach);
+                                if guides != self.guides || !guides.is_empty() {
+                                    self.guides = guides;
+                                    ctx.request_paint();
+                                }
+                                data.transform_selection(&drag.before, |_, shape| shape.translate(delta))
+                            },
+                            DragKind::Resize => {
+                                // the others are scaled by as much as the dragged shape
+                                let pos = data.placement.snap_point(pos);
+                                let mut dragged = drag.before.shapes[index].clone();
+                                dragged.shape_mut().resize_to(pos);
+                                let factor = dragged.shape().size() / drag.before.shapes[index].shape().size();
+                                data.transform_selection(&drag.before, |i, shape| {
+                                    if i == index {
+                                        shape.resize_to(pos);
+                                    } else {
+                                        shape.set_size(shape.size() * factor);
+                                    }
+                                })
+                            },
+                            DragKind::Create => {
+                                let old = data.shapes[index].paint_bounds();
+                                let pos = data.placement.snap_point(pos);
+                                data.update_shape(index, |shape| shape.resize_to(pos))
+                                    .then(|| old.union(data.shapes[index].paint_bounds()))
+                            },
+                        };
+                        if let Some(area) = changed {
+                            // only the area the shapes left and the area they now cover are painted,
+                            // unless the scrollbars change along with them
+                            ctx.request_paint_rect(data.view.screen_rect(area));
+                            self.invalidated = true;
+                        }
+                    }
+                },
+                Event::MouseUp(e) if e.button == MouseButton::Middle && self.pan.is_some() => {
+                    self.pan = None;
+                    ctx.set_active(false);
+                },
+                Event::MouseUp(e) if e.button == MouseButton::Left => {
+                    if self.scroll.take().is_some() || self.band.take().is_some() {
+                        ctx.set_active(false);
+                        ctx.request_paint();
+                    }
+                    if let Some(drag) = self.drag.take() {
+                        ctx.set_active(false);
+                        if !self.guides.is_empty() {
+                            self.guides.clear();
+                            ctx.request_paint();
+                        }
+                        if drag.kind == DragKind::Create {
+                            let item = ActionItem::new(ActionType::Creation, drag.before, data.snapshot());
+                            ctx.submit_command(RECORD_ACTION.with(item), None);
+                        } else if drag.moved {
+                            if !drag.before.shapes.same(&data.shapes) {
+                                let action_type = match drag.kind {
+                                    DragKind::Move { .. } => ActionType::Move,
+                                    _ => ActionType::Adjustment,
+                                };
+                                let item = ActionItem::new(action_type, drag.before, data.snapshot());
+                                ctx.submit_command(RECORD_ACTION.with(item), None);
+                            }
+                        } else if drag.was_selected && drag.kind != DragKind::Resize {
+                            // Deselect by clicking shape again, or pick it out of the group
+                            if data.selected.len() == 1 {
+                                data.selected.clear();
+                            } else {
+                                data.select_only(drag.index);
+                            }
+                        }
+                    }
+                },
+                // zoom around the cursor, the wheel turned sideways (or with shift) pans instead
+                Event::Wheel(e) => {
+                    if e.wheel_delta.y != 0. {
+                        data.view.zoom_at(e.pos, ZOOM_STEP.powf(-e.wheel_delta.y / WHEEL_NOTCH));
+                    }
+                    if e.wheel_delta.x != 0. {
+                        data.view.pan(Vec2::new(-e.wheel_delta.x, 0.));
+                    }
+                    ctx.set_handled();
+                },
+                Event::KeyDown(e) => self.key_down(ctx, e, data),
+                // pasting goes to the widget with focus rather than through the delegate
+                Event::Paste(clipboard) => {
+                    if let Some(item) = clipboard_shapes(clipboard).and_then(|text| data.paste(&text)) {
+                        ctx.submit_command(RECORD_ACTION.with(item), None);
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        // takes part in focus, the rest is passed on to the overlay
+        fn lifecycle(
+            &mut self,
+            ctx: &mut LifeCycleCtx,
+            ev: &LifeCycle,
+            data: &CanvasData,
+            env: &Env,
+        ) {
+            match ev {
+                LifeCycle::WidgetAdded => {
+                    self.content = data.content_bounds();
+                    ctx.register_for_focus();
+                },
+                // the focus ring comes and goes
+                LifeCycle::FocusChanged(_) => ctx.request_paint(),
+                _ => (),
+            }
+            self.overlay.lifecycle(ctx, ev, data, env);
+        }
+
+        fn update(
+            &mut self,
+            ctx: &mut UpdateCtx,
+            old: &CanvasData,
+            new: &CanvasData,
+            env: &Env,
+        ) {
+            if old.tool != new.tool && !self.polygon.is_empty() {
+                self.polygon.clear();
+                self.invalidated = false;
+            }
+            if !old.shapes.same(&new.shapes) {
+                let content = new.content_bounds();
+                // the scrollbars change with the content, so the partial paint is not enough
+                if content != self.content {
+                    self.content = content;
+                    self.invalidated = false;
+                }
+            }
+            if self.invalidated {
+                self.invalidated = false;
+            } else if !old.same(new) {
+                ctx.request_paint();
+            }
+            self.overlay.update(ctx, new, env);
+            if old.is_resizing() != new.is_resizing() {
+                ctx.request_layout();
+            }
+        }
+
+        // sets boundaries, the overlay is placed when it opens
+        fn layout(
+            &mut self,
+            ctx: &mut LayoutCtx,
+            bc: &BoxConstraints,
+            data: &CanvasData,
+            env: &Env,
+        ) -> Size {
+            let size = bc.max();
+            let overlay = self.overlay.layout(ctx, &BoxConstraints::new(Size::ZERO, size), data, env);
+            if !data.is_resizing() {
+                self.overlay_origin = None;
+            }
+            let origin = *self.overlay_origin.get_or_insert_with(|| Canvas::place_overlay(data, overlay, size));
+            self.overlay.set_layout_rect(ctx, data, env, Rect::from_origin_size(origin, overlay));
+            size
+        }
+
+        // Paint the widget, only the shapes in the area being painted are looked at
+        fn paint(&mut self, ctx: &mut PaintCtx, data: &CanvasData, env: &Env) {
+            // paint goes here
+            let rect = ctx.size().to_rect();
+            ctx.clip(rect);
+            ctx.fill(rect, &Color::grey(0.4));
+
+            // widened so shapes whose stroke or handle reaches into the area are included
+            let area = data.view.doc_rect(ctx.region().to_rect()).inflate(HANDLE_SIZE + 1., HANDLE_SIZE + 1.);
+            let view = data.view;
+            ctx.with_save(|ctx| {
+                ctx.transform(view.affine());
+                if data.placement.show_grid {
+                    Canvas::paint_grid(ctx, area, view.zoom);
+                }
+                for i in data.shapes_in(area) {
+                    data.shapes[i].paint(ctx, data.selected.contains(&i));
+                }
+                if let Some(band) = &self.band {
+                    let rect = Rect::from_points(band.start, band.end);
+                    ctx.fill(rect, &Color::WHITE.with_alpha(0.15));
+                    ctx.stroke(rect, &Color::WHITE, 1. / view.zoom);
+                }
+                Canvas::paint_polygon(ctx, &self.polygon, view.zoom);
+                for guide in &self.guides {
+                    ctx.stroke(guide, &GUIDE_COLOR, 1. / view.zoom);
+                }
+                if let Some(cursor) = self.cursor {
+                    let reach = CURSOR_SIZE / view.zoom;
+                    let mut cross = BezPath::new();
+                    cross.move_to(cursor - Vec2::new(reach, 0.));
+                    cross.line_to(cursor + Vec2::new(reach, 0.));
+                    cross.move_to(cursor - Vec2::new(0., reach));
+                    cross.line_to(cursor + Vec2::new(0., reach));
+                    ctx.stroke(cross, &Color::WHITE, 2. / view.zoom);
+                }
+            });
+
+            self.paint_scrollbars(ctx, data, env);
+            if ctx.has_focus() {
+                ctx.stroke(rect.inflate(-1., -1.), &env.get(druid::theme::PRIMARY_LIGHT), 2.);
+            }
+
+            if data.is_resizing() {
+                self.overlay.paint(ctx, data, env);
+            }
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shapes::Item;
+    use druid::{Point, Rect, Vec2};
+    use druid::im::OrdSet;
+
+    fn circle(pos: Point) -> Item {
+        Item::new(Tool::Circle, pos).unwrap()
+    }
 
     // #[ignore]
     #[test]
     fn test() {
         main().expect("Launch Error")
     }
+
+    #[test]
+    fn action_log_undo_redo() {
+        let mut canvas = CanvasData::new();
+        let mut states = vec![canvas.snapshot()];
+        for i in 0..3 {
+            canvas.add_shape(circle(Point::new(i as f64, 0.)));
+            states.push(canvas.snapshot());
+        }
+        let mut log = ActionLog::new(2);
+        for pair in states.windows(2) {
+            log.record(ActionItem::new(ActionType::Creation, pair[0].clone(), pair[1].clone()));
+        }
+
+        // only the last two actions are kept
+        assert!(log.undo().is_some_and(|a| a.before.same(&states[2])));
+        assert!(log.undo().is_some_and(|a| a.before.same(&states[1])));
+        assert!(log.undo().is_none());
+        assert!(log.redo().is_some_and(|a| a.after.same(&states[2])));
+
+        // a new action drops what could have been redone
+        log.record(ActionItem::new(ActionType::Move, states[2].clone(), states[2].clone()));
+        assert!(!log.can_redo());
+        assert!(log.can_undo());
+    }
+
+    #[test]
+    fn zoom_keeps_cursor_and_fit_all_shows_everything() {
+        use druid::Size;
+
+        let mut view = custom::Viewport::default();
+        let cursor = Point::new(120., 80.);
+        let under = view.to_doc(cursor);
+        view.zoom_at(cursor, 2.5);
+        assert!((view.to_screen(under) - cursor).hypot() < 1e-9);
+
+        let mut canvas = CanvasData::new();
+        canvas.add_shape(circle(Point::new(-400., 30.)));
+        canvas.add_shape(circle(Point::new(900., 1200.)));
+        let size = Size::new(300., 200.);
+        canvas.fit_all(size);
+        let visible = canvas.view.doc_rect(size.to_rect());
+        let content = canvas.content_bounds().unwrap();
+        assert_eq!(visible.union(content), visible);
+        assert!(size.to_rect().contains(canvas.view.to_screen(Point::new(-400., 30.))));
+    }
+
+    #[test]
+    fn shapes_hit_test_and_round_trip() {
+        let mut canvas = CanvasData::new();
+        let tools = Tool::ALL.iter().filter(|&&tool| Item::new(tool, Point::ORIGIN).is_some());
+        for (i, &tool) in tools.enumerate() {
+            canvas.add_shape(Item::new(tool, Point::new(i as f64 * 200., 0.)).unwrap());
+        }
+        let triangle = [Point::new(0., 300.), Point::new(100., 300.), Point::new(50., 380.)];
+        canvas.add_shape(Item::polygon(triangle.iter().copied().collect()).unwrap());
+
+        // each shape is hit a little inside its first point, and nothing is between them
+        assert_eq!(canvas.shape_at(Point::new(5., 5.)), Some(0));
+        assert_eq!(canvas.shape_at(Point::new(205., 5.)), Some(1));
+        assert_eq!(canvas.shape_at(Point::new(405., 5.)), Some(2));
+        assert_eq!(canvas.shape_at(Point::new(605., 2.)), Some(3));
+        assert_eq!(canvas.shape_at(Point::new(50., 330.)), Some(4));
+        assert_eq!(canvas.shape_at(Point::new(100., 100.)), None);
+        assert_eq!(canvas.shape_at(Point::new(95., 370.)), None);
+
+        let text = canvas.to_tsv();
+        let loaded = CanvasData::from_tsv(&format!("{}Hexagon	1	2
+Circle	x	0	5
+", text));
+        assert_eq!(loaded.to_tsv(), text);
+        assert_eq!(loaded.shapes_in(Rect::new(590., -10., 610., 10.)), vec![3]);
+    }
+
+    #[test]
+    fn huge_shapes_are_kept_out_of_the_grid_cells() {
+        let mut canvas = CanvasData::from_tsv("Polygon\t-50000\t-50000\t50000\t-50000\t50000\t50000\t-50000\t50000\n");
+        canvas.add_shape(circle(Point::new(0., 0.)));
+        assert_eq!(canvas.shape_at(Point::new(0., 0.)), Some(1));
+        assert_eq!(canvas.shape_at(Point::new(40000., 0.)), Some(0));
+        assert_eq!(canvas.shapes_in(Rect::new(200., 200., 300., 300.)), vec![0]);
+
+        // taken out again when it shrinks back to a normal size
+        canvas.select_only(0);
+        let before = canvas.snapshot();
+        canvas.scale_selection(&before, 0, 100.).unwrap();
+        assert_eq!(canvas.shapes_in(Rect::new(20000., 20000., 20100., 20100.)), Vec::<usize>::new());
+        assert_eq!(canvas.shape_at(Point::new(40000., 0.)), None);
+    }
+
+    #[test]
+    fn pasted_shapes_are_kept_within_the_limits() {
+        let mut canvas = CanvasData::new();
+        let text = "Circle\t0\t0\t0\nCircle\t0\t0\t-5\nEllipse\t0\t0\t10\t0\nLine\t1\t1\t1\t1\n\
+                    Rectangle\t0\t0\t0\t50\nPolygon\t0\t0\t10\t0\t20\t0\nCircle\tNaN\t0\t10\n\
+                    Circle\t1e9\t0\t10\n";
+        assert!(canvas.paste(text).is_none());
+        assert!(canvas.content_bounds().is_none());
+
+        // oversized shapes shrink to the largest size, inverted rectangles are turned the right way round
+        let text = "Rectangle\t#000000ff\t1e9\tnone\t1\t0\t0\t1e6\t1e6\nRectangle\t500\t500\t400\t450\n\
+                    Circle\t0\t0\t1e6\n";
+        canvas.paste(text).unwrap();
+        let saved: Vec<String> = canvas.shapes().iter().map(|item| item.to_tsv()).collect();
+        assert_eq!(saved, vec![
+            "Rectangle\t#000000ff\t32\tnone\t1\t0\t0\t200\t200",
+            "Rectangle\t#000000ff\t2\tnone\t1\t400\t450\t500\t500",
+            "Circle\t#000000ff\t2\tnone\t1\t0\t0\t100",
+        ]);
+    }
+
+    #[test]
+    fn styles_and_order_follow_the_shape() {
+        let mut canvas = CanvasData::new();
+        canvas.add_shape(circle(Point::new(0., 0.)));
+        canvas.add_shape(circle(Point::new(20., 0.)));
+        let overlap = Point::new(10., 0.);
+        assert_eq!(canvas.shape_at(overlap), Some(1));
+
+        canvas.select_only(1);
+        canvas.restyle(&StyleChange::Fill(Some(PALETTE[3].1.clone()))).unwrap();
+        canvas.restyle(&StyleChange::Width(8.)).unwrap();
+        assert!(canvas.restyle(&StyleChange::Width(8.)).is_none());
+        let styled = canvas.shapes()[1].to_tsv();
+
+        // the shape sent to the back keeps its style and the selection, and is no longer on top
+        assert!(canvas.reorder(true).is_none());
+        let action = canvas.reorder(false).unwrap();
+        assert_eq!(canvas.selected, OrdSet::unit(0));
+        assert_eq!(canvas.shapes()[0].to_tsv(), styled);
+        assert_eq!(canvas.shape_at(overlap), Some(1));
+        assert_eq!(canvas.shape_at(Point::new(40., 0.)), Some(0));
+
+        canvas.restore(action.before);
+        assert_eq!(canvas.shape_at(Point::new(40., 0.)), Some(1));
+        let loaded = CanvasData::from_tsv(&canvas.to_tsv());
+        assert_eq!(loaded.to_tsv(), canvas.to_tsv());
+        assert!(loaded.to_tsv().contains("\t8\t#d02020ff\t1\t"));
+    }
+
+    #[test]
+    fn group_selection_moves_duplicates_and_deletes() {
+        let mut canvas = CanvasData::new();
+        for i in 0..4 {
+            canvas.add_shape(circle(Point::new(i as f64 * 100., 0.)));
+        }
+
+        // the band only takes shapes entirely inside it, adding to those already selected
+        canvas.select_in(Rect::new(-30., -30., 130., 30.), &OrdSet::unit(3));
+        assert_eq!(canvas.selected, OrdSet::from(vec![0, 1, 3]));
+        canvas.toggle_selected(3);
+        assert_eq!(canvas.selection_name().as_deref(), Some("2 shapes"));
+
+        // a move works from the snapshot, so the shapes end up where the last step put them
+        let before = canvas.snapshot();
+        canvas.transform_selection(&before, |_, s| s.translate(Vec2::new(5., 0.)));
+        canvas.transform_selection(&before, |_, s| s.translate(Vec2::new(0., 50.))).unwrap();
+        assert_eq!(canvas.shape_at(Point::new(100., 50.)), Some(1));
+        assert_eq!(canvas.shape_at(Point::new(300., 0.)), Some(3));
+
+        // both circles double in size about their own centres
+        let moved = canvas.snapshot();
+        canvas.scale_selection(&moved, 1, 2. * canvas.shapes()[1].shape().size()).unwrap();
+        assert_eq!(canvas.selection_bounds(), Some(Rect::new(-50., 0., 150., 100.)));
+
+        let copied = canvas.duplicate_selection().unwrap();
+        assert_eq!(canvas.shapes().len(), 6);
+        assert_eq!(canvas.selected, OrdSet::from(vec![4, 5]));
+        let deleted = canvas.delete_selection().unwrap();
+        assert!(canvas.selected.is_empty());
+        assert_eq!(canvas.shapes().len(), 4);
+        assert!(deleted.before.same(&copied.after));
+        assert!(canvas.delete_selection().is_none());
+
+        // the index keeps the drawing's bounds up to date through all of that
+        let bounds = canvas.shapes().iter().map(|s| s.paint_bounds()).reduce(|a, b| a.union(b));
+        assert_eq!(canvas.content_bounds(), bounds);
+    }
+
+    #[test]
+    fn pasted_shapes_step_aside_from_their_originals() {
+        let mut canvas = CanvasData::new();
+        canvas.add_shape(circle(Point::new(0., 0.)));
+        canvas.add_shape(Item::new(Tool::Line, Point::new(100., 0.)).unwrap());
+        canvas.restyle(&StyleChange::Fill(Some(Color::WHITE)));
+        assert!(canvas.selection_tsv().is_none());
+
+        canvas.select_in(Rect::new(-50., -50., 200., 50.), &OrdSet::new());
+        canvas.restyle(&StyleChange::Fill(Some(PALETTE[3].1.clone()))).unwrap();
+        let copied = canvas.selection_tsv().unwrap();
+        let svg = canvas.selection_svg().unwrap();
+        assert!(svg.starts_with("<svg") && svg.matches("<path").count() == 2);
+        assert!(svg.contains("fill=\"#d02020\"") && svg.contains("fill=\"none\""));
+
+        // pasting over the originals moves the copies aside, a second paste moves further
+        canvas.paste(&copied).unwrap();
+        canvas.paste(&copied).unwrap();
+        assert_eq!(canvas.selected, OrdSet::from(vec![4, 5]));
+        let step = Vec2::new(20., 20.);
+        assert_eq!(canvas.selection_bounds(), canvas.shapes().iter().take(2)
+            .map(|item| item.shape().bounds() + step)
+            .reduce(|a, b| a.union(b)));
+
+        // into an empty drawing they land where they were copied from
+        let mut other = CanvasData::new();
+        let action = other.paste(&copied).unwrap();
+        assert_eq!(other.to_tsv(), copied);
+        other.restore(action.before);
+        assert!(other.to_tsv().is_empty() && other.selected.is_empty());
+        assert!(other.paste("not a shape").is_none());
+    }
+
+    #[test]
+    fn opening_and_new_replace_the_drawing_undoably() {
+        let mut canvas = CanvasData::new();
+        assert!(canvas.replace_drawing("").is_none());
+        canvas.add_shape(circle(Point::new(0., 0.)));
+        let saved = canvas.to_tsv();
+
+        let mut other = CanvasData::new();
+        other.add_shape(Item::new(Tool::Line, Point::new(100., 0.)).unwrap());
+        other.select_next(true);
+        let action = other.replace_drawing(&saved).unwrap();
+        assert_eq!(other.to_tsv(), saved);
+        assert!(other.selected.is_empty());
+        other.restore(action.before);
+        assert_eq!(other.shapes().len(), 1);
+
+        canvas.replace_drawing("").unwrap();
+        assert!(canvas.shapes().is_empty());
+    }
+
+    #[test]
+    fn keyboard_steps_through_nudges_and_resizes() {
+        let mut canvas = CanvasData::new();
+        for i in 0..3 {
+            canvas.add_shape(circle(Point::new(i as f64 * 100., 0.)));
+        }
+        canvas.select_next(true);
+        assert_eq!(canvas.primary(), Some(2));
+        canvas.select_next(false);
+        assert_eq!(canvas.primary(), Some(0));
+        canvas.select_next(false);
+        assert_eq!(canvas.selected, OrdSet::unit(1));
+
+        canvas.nudge_selection(Vec2::new(0., 10.)).unwrap();
+        assert_eq!(canvas.shapes()[1].shape().handle(), Point::new(125., 10.));
+
+        // the view moves just far enough to show the shape
+        let size = druid::Size::new(100., 100.);
+        canvas.view.reveal(canvas.selection_bounds().unwrap(), size);
+        assert_eq!(canvas.view.doc_rect(size.to_rect()), Rect::new(25., -15., 125., 85.));
+
+        let before = canvas.shapes()[1].shape().size();
+        canvas.resize_selection_by(5.).unwrap();
+        assert_eq!(canvas.shapes()[1].shape().size(), before + 5.);
+        canvas.resize_selection_by(MAX_RADIUS).unwrap();
+        assert!(canvas.resize_selection_by(1.).is_none());
+    }
+
+    #[test]
+    fn placement_snaps_lines_up_and_arranges() {
+        let mut canvas = CanvasData::new();
+        canvas.add_shape(circle(Point::new(0., 0.)));
+        canvas.add_shape(circle(Point::new(200., 103.)));
+        canvas.add_shape(circle(Point::new(70., 300.)));
+        canvas.select_only(1);
+        let before = canvas.snapshot();
+        let area = Rect::new(-500., -500., 500., 500.);
+
+        // the dragged centre lands on the grid, and 3 short of the first circle's centre it lines up
+        canvas.placement = custom::Placement { show_grid: true, snap: true, guides: false };
+        let (delta, guides) = canvas.place_selection(&before, 1, Vec2::new(-9., 0.), area, 6.);
+        assert_eq!((delta, guides.len()), (Vec2::new(0., -3.), 0));
+        canvas.placement.guides = true;
+        let (delta, guides) = canvas.place_selection(&before, 1, Vec2::new(0., -100.), area, 6.);
+        assert_eq!(delta, Vec2::new(0., -103.));
+        assert_eq!(guides, vec![druid::kurbo::Line::new((-25., 0.), (225., 0.))]);
+
+        // align needs two shapes, distribute three
+        assert!(canvas.arrange(custom::Arrange::Left).is_none());
+        canvas.select_in(area, &OrdSet::new());
+        canvas.arrange(custom::Arrange::Top).unwrap();
+        assert!(canvas.arrange(custom::Arrange::Top).is_none());
+        assert!(canvas.shapes().iter().all(|item| item.shape().bounds().y0 == -25.));
+        canvas.arrange(custom::Arrange::Across).unwrap();
+        let centres: Vec<f64> = canvas.shapes().iter().map(|item| item.shape().bounds().center().x).collect();
+        assert_eq!(centres, vec![0., 200., 100.]);
+    }
+
+    /// Compares hit-testing through the spatial index with checking every shape.
+    /// Run with `cargo test --release bench_spatial_index -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_spatial_index() {
+        use std::time::Instant;
+
+        // a small linear congruential generator keeps the layout the same between runs
+        let mut seed: u64 = 7;
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as f64 / (1u64 << 31) as f64 * 10000.
+        };
+
+        let mut canvas = CanvasData::new();
+        let start = Instant::now();
+        for _ in 0..100_000 {
+            canvas.add_shape(circle(Point::new(next(), next())));
+        }
+        println!("indexed 100000 circles in {:?}", start.elapsed());
+        let points: Vec<Point> = (0..10_000).map(|_| Point::new(next(), next())).collect();
+
+        let start = Instant::now();
+        let indexed: Vec<Option<usize>> = points.iter().map(|&p| canvas.shape_at(p)).collect();
+        let grid_time = start.elapsed();
+
+        let start = Instant::now();
+        let linear: Vec<Option<usize>> = points.iter()
+            .map(|&p| {
+                canvas.shapes().iter()
+                    .map(|item| item.shape())
+                    .enumerate()
+                    .filter(|(_, shape)| shape.contains(p))
+                    .map(|(i, _)| i)
+                    .last()
+            })
+            .collect();
+        let linear_time = start.elapsed();
+
+        println!("10000 hit-tests: grid {:?}, linear {:?}", grid_time, linear_time);
+        assert_eq!(indexed, linear);
+        assert!(grid_time * 10 < linear_time);
+
+        let visible = canvas.shapes_in(Rect::new(0., 0., 500., 500.));
+        println!("{} circles to paint in a 500x500 view", visible.len());
+        assert!(visible.len() < 1000);
+    }
 }
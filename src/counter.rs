@@ -1,46 +1,688 @@
 //! # A simple counting application
+//! Any number of named counters can be kept, for tallying several things at once.
+//! Each count moves up or down by a chosen step and stops at the bounds rather than overflowing.
+//! With no text field selected `+`, `-` and `0` count up, count down and reset the selected
+//! counter, which is the one last counted.
+//! Every change is logged and the tallies and the log can be exported to CSV. The counters and
+//! their settings are saved when the window closes, the log only covers the current run.
 
 use druid::{
     AppLauncher, WindowDesc, Widget, PlatformError,
-    widget::{Button, Label, Flex, Align},
-    Data, Lens, Size
+    widget::{Button, Label, Flex, Align, TextBox, Stepper, Controller, List, Scroll},
+    Data, Lens, Size, WidgetExt, Event, EventCtx, Env, Key, Color, AppDelegate, DelegateCtx,
+    WindowId, Selector, Target, Command, FileDialogOptions, FileSpec,
 };
+use druid::im::Vector;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::persist::{self, Persist};
+use crate::dates;
+use crate::menus::{self, MenuState, TaskMenu};
 
 const WINDOW_TITLE: &str = "Counter";
-const WINDOW_SIZE: Size = Size::new(200., 75.);
+const WINDOW_SIZE: Size = Size::new(560., 400.);
+const WINDOW_SIZE_MIN: Size = Size::new(480., 250.);
+const PADDING: f64 = 8.;
+const NAME_WIDTH: f64 = 140.;
+const COUNT_WIDTH: f64 = 60.;
+const BOUND_WIDTH: f64 = 80.;
+const MAX_STEP: f64 = 1_000_000.;
+const CSV_FILE: FileSpec = FileSpec::new("CSV", &["csv"]);
+/// Counters opened and saved from the File menu, in the format of the save file
+const COUNTERS_FILE: FileSpec = FileSpec::new("Counters", &["tsv"]);
+
+const TXT_CLR_INVALID: Key<Color> = Key::new("app.txt.clr.invalid");
+const ROW_CLR_BORDER: Key<Color> = Key::new("app.row.clr.border");
+
+/// Counts a counter up, down or back to zero
+const ADJUST: Selector<(u64, Change)> = Selector::new("app.counter.adjust");
+const REMOVE_COUNTER: Selector<u64> = Selector::new("app.counter.remove");
+/// Asks where to export the tallies and the log, the menu's save panel is for the counters
+const EXPORT: Selector = Selector::new("app.counter.export");
 
 fn build_ui() -> impl Widget<AppData> {
-    let layout = Flex::row()
-        .with_child(Label::new(|data: &AppData, _: &_| {
-            format!("{}", data.count)
-        }))
-        .with_spacer(25.)
-        .with_child(Button::new("Count")
-                             .on_click(|_, data: &mut AppData, _: &_| data.count += 1));
+    let header = Flex::row()
+        .with_child(TextBox::new().fix_width(NAME_WIDTH).lens(AppData::new_name))
+        .with_spacer(PADDING)
+        .with_child(Button::new("Add Counter")
+            .on_click(|_, data: &mut AppData, _: &_| data.add_counter()))
+        .with_flex_spacer(1.)
+        .with_child(Button::new("Export").on_click(|ctx, _data: &mut AppData, _| {
+            ctx.submit_command(EXPORT, None);
+        }));
 
-    Align::centered(layout)
+    // shared by every counter, left empty there is no bound
+    let settings = Flex::row()
+        .with_child(Label::new("Step: "))
+        .with_child(Label::new(|data: &AppData, _: &_| format!("{}", data.step)))
+        .with_child(Stepper::new()
+            .with_range(1., MAX_STEP)
+            .with_step(1.)
+            .lens(AppData::step))
+        .with_spacer(PADDING * 2.)
+        .with_child(Label::new("Min: "))
+        .with_child(TextBox::new()
+            .fix_width(BOUND_WIDTH)
+            .lens(AppData::min_text)
+            .env_scope(|env, data: &AppData| scope_invalid(env, data.min_invalid)))
+        .with_spacer(PADDING)
+        .with_child(Label::new("Max: "))
+        .with_child(TextBox::new()
+            .fix_width(BOUND_WIDTH)
+            .lens(AppData::max_text)
+            .env_scope(|env, data: &AppData| scope_invalid(env, data.max_invalid)));
 
+    let counters = Scroll::new(List::new(build_counter))
+        .vertical()
+        .lens(AppData::counters);
+
+    Flex::column()
+        .with_child(header)
+        .with_spacer(PADDING)
+        .with_child(Align::left(settings))
+        .with_spacer(PADDING)
+        .with_flex_child(counters, 1.)
+        .padding(PADDING * 2.)
+        .controller(CounterControl)
+        .controller(menus::MenuBar)
+}
+
+/// A row for a single counter, the buttons are handled by the CounterControl so changes can be logged
+fn build_counter() -> impl Widget<CounterData> {
+    Flex::row()
+        .with_child(TextBox::new().fix_width(NAME_WIDTH).lens(CounterData::name))
+        .with_spacer(PADDING)
+        .with_child(Button::new("−")
+            .on_click(|ctx, data: &mut CounterData, _: &_| {
+                ctx.submit_command(ADJUST.with((data.id, Change::Decrement)), None);
+            }))
+        .with_child(Label::new(|data: &CounterData, _: &_| format!("{}", data.count))
+            .center()
+            .fix_width(COUNT_WIDTH))
+        .with_child(Button::new("+")
+            .on_click(|ctx, data: &mut CounterData, _: &_| {
+                ctx.submit_command(ADJUST.with((data.id, Change::Increment)), None);
+            }))
+        .with_spacer(PADDING)
+        .with_child(Button::new("Reset")
+            .on_click(|ctx, data: &mut CounterData, _: &_| {
+                ctx.submit_command(ADJUST.with((data.id, Change::Reset)), None);
+            }))
+        .with_spacer(PADDING)
+        .with_child(Label::new(|data: &CounterData, _: &_| {
+            match data.last_increment {
+                Some(time) => format!("last counted {}", format_time(time)),
+                None => String::new(),
+            }
+        }))
+        .with_flex_spacer(1.)
+        .with_child(Button::new("✕")
+            .on_click(|ctx, data: &mut CounterData, _: &_| {
+                ctx.submit_command(REMOVE_COUNTER.with(data.id), None);
+            }))
+        .padding(PADDING / 2.)
+        .border(ROW_CLR_BORDER, 1.)
+        .env_scope(|env, data: &CounterData| {
+            if data.selected {
+                env.set(ROW_CLR_BORDER, env.get(druid::theme::PRIMARY_LIGHT));
+            }
+        })
+        .padding((0., PADDING / 4.))
+}
+
+/// Shows a bound in red when it can't be read
+fn scope_invalid(env: &mut Env, invalid: bool) {
+    if invalid {
+        env.set(druid::theme::LABEL_COLOR, env.get(TXT_CLR_INVALID));
+    }
 }
 
 pub fn main() -> Result<(), PlatformError> {
-    let data = AppData::new();
+    let data = persist::restore().unwrap_or_else(AppData::new);
     let window = WindowDesc::new(build_ui)
         .window_size(WINDOW_SIZE)
+        .with_min_size(WINDOW_SIZE_MIN)
         .title(WINDOW_TITLE)
-        .resizable(false);
-    AppLauncher::with_window(window).launch(data)?;
+        .menu(menus::menu_bar(&data.menu_state()));
+    AppLauncher::with_window(window)
+        .delegate(Delegate::default())
+        .configure_env(|env, _state| {
+            env.set(TXT_CLR_INVALID, Color::rgb(0.85, 0.05, 0.1));
+            env.set(ROW_CLR_BORDER, Color::grey(0.6));
+        })
+        .launch(data)?;
     Ok(())
 }
 
+/// How a count was changed
+#[derive(Clone, Copy, Data, PartialEq, Debug)]
+enum Change {
+    Increment,
+    Decrement,
+    Reset,
+}
+
+/// A line in the log, times are seconds since the unix epoch
+#[derive(Clone, Data)]
+struct TallyEvent {
+    time: u64,
+    // the name when the change was made, the counter may have been renamed since
+    name: String,
+    change: Change,
+    count: i64,
+}
+
+/// A single named counter
+#[derive(Clone, Data, Lens)]
+struct CounterData {
+    id: u64,
+    name: String,
+    count: i64,
+    last_increment: Option<u64>,
+    // the counter the keyboard shortcuts apply to
+    selected: bool,
+}
+
 #[derive(Clone, Data, Lens)]
 struct AppData {
-    count: u64,
+    counters: Vector<CounterData>,
+    // name for the next counter added, left empty they are numbered
+    new_name: String,
+    next_id: u64,
+    log: Vector<TallyEvent>,
+    // whole numbers only, the stepper works in floats
+    step: f64,
+    min_text: String,
+    max_text: String,
+    min_invalid: bool,
+    max_invalid: bool,
 }
 
 impl AppData {
     fn new() -> AppData {
+        let mut data = AppData::empty(1.);
+        data.add_counter();
+        data
+    }
+
+    /// No counters yet
+    fn empty(step: f64) -> AppData {
         AppData {
-            count: 0
+            counters: Vector::new(),
+            new_name: String::new(),
+            next_id: 0,
+            log: Vector::new(),
+            step,
+            min_text: String::new(),
+            max_text: String::new(),
+            min_invalid: false,
+            max_invalid: false,
+        }
+    }
+
+    fn add_counter(&mut self) {
+        let name = match self.new_name.trim() {
+            "" => format!("Counter {}", self.next_id + 1),
+            name => name.to_string(),
+        };
+        let (min, max) = self.bounds();
+        self.counters.push_back(CounterData {
+            id: self.next_id,
+            name,
+            count: 0i64.clamp(min, max),
+            last_increment: None,
+            selected: false,
+        });
+        self.select(self.next_id);
+        self.next_id += 1;
+    }
+
+    fn remove_counter(&mut self, id: u64) {
+        self.counters.retain(|c| c.id != id);
+    }
+
+    fn select(&mut self, id: u64) {
+        for counter in self.counters.iter_mut() {
+            counter.selected = counter.id == id;
+        }
+    }
+
+    fn selected(&self) -> Option<u64> {
+        self.counters.iter().find(|c| c.selected).map(|c| c.id)
+    }
+
+    /// The lowest and highest count allowed.
+    /// Bounds that are blank or can't be read are left out, as are both if they cross.
+    fn bounds(&self) -> (i64, i64) {
+        let min = parse_bound(&self.min_text).unwrap_or(None).unwrap_or(i64::MIN);
+        let max = parse_bound(&self.max_text).unwrap_or(None).unwrap_or(i64::MAX);
+        if min <= max {
+            (min, max)
+        } else {
+            (i64::MIN, i64::MAX)
+        }
+    }
+
+    /// Changes a counter within the bounds, selects it and logs the change
+    fn adjust(&mut self, id: u64, change: Change) {
+        let (min, max) = self.bounds();
+        let step = self.step as i64;
+        let time = now();
+        self.select(id);
+
+        let counter = match self.counters.iter_mut().find(|c| c.id == id) {
+            Some(counter) => counter,
+            None => return,
+        };
+        counter.count = match change {
+            Change::Increment => counter.count.saturating_add(step).min(max),
+            Change::Decrement => counter.count.saturating_sub(step).max(min),
+            Change::Reset => 0i64.clamp(min, max),
+        };
+        if change == Change::Increment {
+            counter.last_increment = Some(time);
+        }
+        let event = TallyEvent {
+            time,
+            name: counter.name.clone(),
+            change,
+            count: counter.count,
+        };
+        self.log.push_back(event);
+    }
+
+    /// Checks the bounds after they are edited and moves every count back inside them.
+    /// Counts moved this way are not logged, the log only holds what was counted.
+    fn update_bounds(&mut self) {
+        let min = parse_bound(&self.min_text);
+        let max = parse_bound(&self.max_text);
+        let crossed = matches!((min, max), (Some(Some(min)), Some(Some(max))) if min > max);
+        self.min_invalid = min.is_none() || crossed;
+        self.max_invalid = max.is_none() || crossed;
+
+        let (min, max) = self.bounds();
+        for counter in self.counters.iter_mut() {
+            counter.count = counter.count.clamp(min, max);
+        }
+    }
+
+    /// One line per counter: name, count and time of the last increment
+    fn tallies_csv(&self) -> String {
+        let mut csv = String::from("name,count,last_increment\n");
+        for counter in self.counters.iter() {
+            csv.push_str(&format!("{},{},{}\n",
+                                  csv_field(&counter.name), counter.count,
+                                  counter.last_increment.map(format_timestamp).unwrap_or_default()));
+        }
+        csv
+    }
+
+    /// Every change in the order it was made
+    fn log_csv(&self) -> String {
+        let mut csv = String::from("time,name,change,count\n");
+        for event in self.log.iter() {
+            csv.push_str(&format!("{},{},{:?},{}\n",
+                                  format_timestamp(event.time), csv_field(&event.name),
+                                  event.change, event.count));
+        }
+        csv
+    }
+
+    /// The settings on the first line: step, min and max separated by tabs.
+    /// Then one counter per line: name, count and the time of the last increment if any.
+    fn to_tsv(&self) -> String {
+        let mut tsv = format!("{}\t{}\t{}\n", self.step, self.min_text.trim(), self.max_text.trim());
+        for counter in self.counters.iter() {
+            tsv.push_str(&format!("{}\t{}\t{}\n",
+                                  counter.name.replace(['\t', '\n'], " "), counter.count,
+                                  counter.last_increment.map(|t| t.to_string()).unwrap_or_default()));
+        }
+        tsv
+    }
+
+    /// Reads the format written by to_tsv, None if the settings can't be read.
+    /// Counter lines that can't be read are skipped.
+    /// A save from before there were several counters holds a single line of
+    /// count, step, min and max, it becomes the first counter.
+    fn from_tsv(text: &str) -> Option<AppData> {
+        let mut lines = text.lines();
+        let settings: Vec<&str> = lines.next()?.split('\t').collect();
+        let (count, step, min, max) = match settings.as_slice() {
+            [step, min, max] => (None, *step, *min, *max),
+            [count, step, min, max] => (Some(count.parse::<i64>().ok()?), *step, *min, *max),
+            _ => return None,
+        };
+
+        let mut data = AppData::empty(step.parse::<f64>().ok()?.clamp(1., MAX_STEP).round());
+        data.min_text = min.to_string();
+        data.max_text = max.to_string();
+
+        if let Some(count) = count {
+            data.add_counter();
+            data.counters[0].count = count;
+        }
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if let [name, count, last] = fields.as_slice() {
+                let last_increment = match *last {
+                    "" => None,
+                    last => match last.parse() {
+                        Ok(time) => Some(time),
+                        Err(_) => continue,
+                    },
+                };
+                if let Ok(count) = count.parse() {
+                    data.counters.push_back(CounterData {
+                        id: data.next_id,
+                        name: name.to_string(),
+                        count,
+                        last_increment,
+                        selected: false,
+                    });
+                    data.next_id += 1;
+                }
+            }
+        }
+        if data.counters.is_empty() {
+            data.add_counter();
+        }
+        data.select(data.counters[0].id);
+        data.update_bounds();
+        Some(data)
+    }
+}
+
+/// Reads a bound, Some(None) when left blank and None when it isn't a whole number
+fn parse_bound(text: &str) -> Option<Option<i64>> {
+    match text.trim() {
+        "" => Some(None),
+        text => text.parse().ok().map(Some),
+    }
+}
+
+/// Seconds since the unix epoch
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// The time of day as `hh:mm:ss`, in UTC as there is no time zone information to hand
+fn format_time(time: u64) -> String {
+    format!("{:02}:{:02}:{:02} UTC", time / 3600 % 24, time / 60 % 60, time % 60)
+}
+
+/// An ISO 8601 timestamp in UTC, `yyyy-mm-ddThh:mm:ssZ`
+fn format_timestamp(time: u64) -> String {
+    let (year, month, day) = dates::civil_from_days((time / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}T{}Z", year, month, day, &format_time(time)[..8])
+}
+
+/// Quotes a CSV field if it holds a comma, quote or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Handles the keyboard shortcuts and the commands sent from the counter rows, and keeps the
+/// counts inside the bounds as they are edited.
+/// Keys only reach this when it or one of its children has focus, so it takes focus when the
+/// window opens and on every click, a text field that is clicked takes it back.
+struct CounterControl;
+
+impl <W: Widget<AppData>> Controller<AppData, W> for CounterControl {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        let old_min = data.min_text.clone();
+        let old_max = data.max_text.clone();
+
+        // a child's focus request wins over this one, so this has to come first
+        if let Event::MouseDown(_) = event {
+            ctx.request_focus();
+        }
+
+        child.event(ctx, event, data, env);
+
+        match event {
+            Event::WindowConnected => ctx.request_focus(),
+            // a text field being typed in has focus instead, the keys are left to it
+            Event::KeyDown(key) if ctx.is_focused() => {
+                let change = match key.text() {
+                    Some("+") | Some("=") => Some(Change::Increment),
+                    Some("-") => Some(Change::Decrement),
+                    Some("0") => Some(Change::Reset),
+                    _ => None,
+                };
+                if let (Some(change), Some(id)) = (change, data.selected()) {
+                    data.adjust(id, change);
+                }
+            },
+            Event::Command(cmd) if cmd.is(ADJUST) => {
+                let (id, change) = *cmd.get_unchecked(ADJUST);
+                data.adjust(id, change);
+            },
+            Event::Command(cmd) if cmd.is(REMOVE_COUNTER) => {
+                data.remove_counter(*cmd.get_unchecked(REMOVE_COUNTER));
+            },
+            _ => (),
+        }
+
+        if data.min_text != old_min || data.max_text != old_max {
+            data.update_bounds();
+        }
+    }
+}
+
+/// The format hasn't changed since before there were versions
+impl Persist for AppData {
+    const FILE: &'static str = "counter.tsv";
+    const VERSION: u32 = 1;
+
+    fn to_save(&self) -> String {
+        self.to_tsv()
+    }
+
+    fn from_save(text: &str, _version: u32) -> Option<Self> {
+        AppData::from_tsv(text)
+    }
+}
+
+/// The text boxes take cut, copy and paste, there is nothing to undo
+impl TaskMenu for AppData {
+    fn menu_state(&self) -> MenuState {
+        MenuState {
+            new: true,
+            open: true,
+            save: true,
+            file: Some(COUNTERS_FILE),
+            cut: true,
+            copy: true,
+            paste: true,
+            ..MenuState::default()
+        }
+    }
+}
+
+/// Writes the exports and saves the counters when the window closes
+#[derive(Default)]
+struct Delegate {
+    // set by the export button until its save panel is shown
+    export_asked: bool,
+    // whether the file chosen in the save panel is for the export or the counters
+    exporting: bool,
+}
+
+impl AppDelegate<AppData> for Delegate {
+    fn command(
+        &mut self,
+        ctx: &mut DelegateCtx,
+        target: Target,
+        cmd: &Command,
+        data: &mut AppData,
+        _env: &Env,
+    ) -> bool {
+        if cmd.is(EXPORT) {
+            self.export_asked = true;
+            let options = FileDialogOptions::new()
+                .allowed_types(vec![CSV_FILE])
+                .default_type(CSV_FILE);
+            ctx.submit_command(druid::commands::SHOW_SAVE_PANEL.with(options), target);
+            false
+        } else if cmd.is(druid::commands::SHOW_SAVE_PANEL) {
+            // both panels end in SAVE_FILE, so remember which one this is
+            self.exporting = std::mem::take(&mut self.export_asked);
+            true
+        } else if cmd.is(druid::commands::NEW_FILE) {
+            *data = AppData::new();
+            false
+        } else if let Some(file) = cmd.get(druid::commands::OPEN_FILE) {
+            match std::fs::read_to_string(file.path()) {
+                Ok(text) => match AppData::from_tsv(&text) {
+                    Some(opened) => *data = opened,
+                    None => println!("Error: {} is not a counters file", file.path().display()),
+                },
+                Err(error) => println!("Error: {}", error),
+            }
+            false
+        } else if let Some(Some(file)) = cmd.get(druid::commands::SAVE_FILE) {
+            let path = file.path();
+            if !self.exporting {
+                if let Err(error) = std::fs::write(path, data.to_tsv()) {
+                    println!("Error: {}", error);
+                }
+                return false;
+            }
+            // the log goes next to the tallies, `tally.csv` and `tally-log.csv`
+            let stem = path.file_stem().map_or("tally".into(), |s| s.to_string_lossy());
+            let log_path = path.with_file_name(format!("{}-log.csv", stem));
+            for (path, csv) in [(path, data.tallies_csv()), (&log_path, data.log_csv())].iter() {
+                if let Err(error) = std::fs::write(path, csv) {
+                    println!("Error: {}", error);
+                }
+            }
+            false
+        } else {
+            true
+        }
+    }
+
+    fn window_removed(
+        &mut self,
+        _id: WindowId,
+        data: &mut AppData,
+        _env: &Env,
+        _ctx: &mut DelegateCtx,
+    ) {
+        if let Err(error) = persist::store(data) {
+            println!("Error: {}", error);
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_bounds(data: &mut AppData, min: &str, max: &str) {
+        data.min_text = min.to_string();
+        data.max_text = max.to_string();
+        data.update_bounds();
+    }
+
+    #[test]
+    fn counts_stop_at_the_ends_of_i64() {
+        let mut data = AppData::new();
+        let id = data.counters[0].id;
+        data.step = MAX_STEP;
+        data.counters[0].count = i64::MAX - 1;
+        data.adjust(id, Change::Increment);
+        assert_eq!(data.counters[0].count, i64::MAX);
+        data.adjust(id, Change::Increment);
+        assert_eq!(data.counters[0].count, i64::MAX);
+
+        data.counters[0].count = i64::MIN + 1;
+        data.adjust(id, Change::Decrement);
+        data.adjust(id, Change::Decrement);
+        assert_eq!(data.counters[0].count, i64::MIN);
+        assert_eq!(data.log.len(), 4);
+    }
+
+    #[test]
+    fn counts_move_inside_new_bounds() {
+        let mut data = AppData::new();
+        let id = data.counters[0].id;
+        data.step = 5.;
+        data.adjust(id, Change::Increment);
+        data.adjust(id, Change::Increment);
+        set_bounds(&mut data, "", "7");
+        assert_eq!(data.counters[0].count, 7);
+        data.adjust(id, Change::Increment);
+        assert_eq!(data.counters[0].count, 7);
+
+        // zero is outside, so a reset goes to the nearest bound
+        set_bounds(&mut data, "3", "7");
+        data.adjust(id, Change::Reset);
+        assert_eq!(data.counters[0].count, 3);
+        data.adjust(id, Change::Decrement);
+        assert_eq!(data.counters[0].count, 3);
+
+        // the clamping isn't logged, only the counting is
+        assert_eq!(data.log.iter().map(|e| e.count).collect::<Vec<_>>(), vec![5, 10, 7, 3, 3]);
+    }
+
+    #[test]
+    fn crossed_bounds_are_marked_and_ignored() {
+        let mut data = AppData::new();
+        let id = data.counters[0].id;
+        set_bounds(&mut data, "10", "-10");
+        assert!(data.min_invalid && data.max_invalid);
+        assert_eq!(data.bounds(), (i64::MIN, i64::MAX));
+        assert_eq!(data.counters[0].count, 0);
+        data.adjust(id, Change::Decrement);
+        assert_eq!(data.counters[0].count, -1);
+
+        set_bounds(&mut data, "x", "-10");
+        assert_eq!((data.min_invalid, data.max_invalid), (true, false));
+        assert_eq!(data.counters[0].count, -10);
+    }
+
+    #[test]
+    fn a_single_counter_save_becomes_the_first_counter() {
+        let data = AppData::from_tsv("42\t5\t\t40\n").unwrap();
+        assert_eq!(data.counters.len(), 1);
+        assert_eq!((data.counters[0].name.as_str(), data.counters[0].count), ("Counter 1", 40));
+        assert!(data.counters[0].selected);
+        assert_eq!((data.step, data.min_text.as_str(), data.max_text.as_str()), (5., "", "40"));
+
+        // saved again in the current format, with the count as it was clamped
+        let data = AppData::from_tsv(&data.to_tsv()).unwrap();
+        assert_eq!(data.counters.len(), 1);
+        assert_eq!(data.counters[0].count, 40);
+
+        assert!(AppData::from_tsv("many\t1\t\t\n").is_none());
+        assert!(AppData::from_tsv("1\t1\n").is_none());
+    }
+
+    #[test]
+    fn names_are_quoted_in_the_csv() {
+        let mut data = AppData::from_tsv("1\t\t\nplain\t3\t\nred, green\t2\t\nthe \"big\" one\t1\t86400\n").unwrap();
+        assert_eq!(data.tallies_csv(), "name,count,last_increment\n\
+                                        plain,3,\n\
+                                        \"red, green\",2,\n\
+                                        \"the \"\"big\"\" one\",1,1970-01-02T00:00:00Z\n");
+
+        let id = data.counters[1].id;
+        data.adjust(id, Change::Decrement);
+        let log = data.log_csv();
+        assert!(log.starts_with("time,name,change,count\n"));
+        assert!(log.ends_with(",\"red, green\",Decrement,1\n"));
+    }
+}
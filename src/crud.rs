@@ -1,22 +1,28 @@
 //! # A more advanced application Create-Read-Update-Delete
 
-use druid::{AppLauncher, WindowDesc, Widget, PlatformError, Data, Lens, Size, WidgetExt, Color, LensExt};
+use druid::{AppLauncher, WindowDesc, Widget, PlatformError, Data, Lens, Size, WidgetExt, Color, LensExt,
+            AppDelegate, DelegateCtx, Target, Command, Env, WindowId};
 use druid::widget::{Label, Flex, Align, TextBox, Button, Scroll, List, CrossAxisAlignment, MainAxisAlignment, Either};
 use druid::lens::{self};
 use druid::im::{Vector, vector};
 
+use crate::menus::{self, MenuState, TaskMenu};
+use crate::persist::{self, Persist};
+
 const WINDOW_TITLE: &str = "CRUD";
 const WINDOW_SIZE: Size = Size::new(500., 350.);
 const WINDOW_SIZE_MIN: Size = Size::new(400., 250.);
 const PADDING: f64 = 8.;
 
 pub fn main()-> Result<(), PlatformError>  {
-    let data = AppData::new();
+    let data = persist::restore().unwrap_or_else(AppData::new);
     let window = WindowDesc::new(build_ui)
         .window_size(WINDOW_SIZE)
         .with_min_size(WINDOW_SIZE_MIN)
-        .title(WINDOW_TITLE);
+        .title(WINDOW_TITLE)
+        .menu(menus::menu_bar(&data.menu_state()));
     AppLauncher::with_window(window)
+        .delegate(Delegate)
         .launch(data)?;
     Ok(())
 }
@@ -50,6 +56,17 @@ impl AppData {
         }
     }
 
+    /// An empty list with nothing typed in
+    fn empty() -> Self {
+        AppData {
+            prefix: "".into(),
+            name: "".into(),
+            surname: "".into(),
+            list: Vector::new(),
+            current: None
+        }
+    }
+
     fn filter(&self) -> Vector<ListItem> {
         let f = self.prefix.to_lowercase();
         self.list
@@ -169,6 +186,64 @@ fn build_ui() -> impl Widget<AppData> {
         .with_spacer(PADDING)
         .with_child(foot)
         .padding(PADDING * 2.)
+        .controller(menus::MenuBar)
+}
+
+/// The list is kept between runs, one "Surname, Name" per line.
+/// A file with any other line in it isn't one of ours, and is left for persist to move aside
+impl Persist for AppData {
+    const FILE: &'static str = "crud.txt";
+    const VERSION: u32 = 1;
+
+    fn to_save(&self) -> String {
+        self.list.iter().map(|s| format!("{}\n", s.item.replace('\n', " "))).collect()
+    }
+
+    fn from_save(text: &str, version: u32) -> Option<Self> {
+        if version != 1 {
+            return None;
+        }
+        let list = text.lines()
+            .enumerate()
+            .map(|(i, s)| match s.contains(", ") && !s.contains(char::is_control) {
+                true => Some(ListItem::new(i, s.into())),
+                false => None,
+            })
+            .collect::<Option<Vector<ListItem>>>()?;
+        Some(AppData { list, ..AppData::empty() })
+    }
+}
+
+/// New starts an empty list, the text boxes take cut, copy and paste
+impl TaskMenu for AppData {
+    fn menu_state(&self) -> MenuState {
+        MenuState {
+            new: !self.list.is_empty(),
+            cut: true,
+            copy: true,
+            paste: true,
+            ..MenuState::default()
+        }
+    }
+}
+
+struct Delegate;
+
+impl AppDelegate<AppData> for Delegate {
+    fn command(&mut self, _ctx: &mut DelegateCtx, _target: Target, cmd: &Command, data: &mut AppData, _env: &Env) -> bool {
+        if cmd.is(druid::commands::NEW_FILE) {
+            *data = AppData::empty();
+            false
+        } else {
+            true
+        }
+    }
+
+    fn window_removed(&mut self, _id: WindowId, data: &mut AppData, _env: &Env, _ctx: &mut DelegateCtx) {
+        if let Err(error) = persist::store(data) {
+            println!("Error: {}", error);
+        }
+    }
 }
 
 #[derive(Clone, Data, Lens)]
@@ -208,4 +283,4 @@ fn new_item() -> impl Widget<(Option<usize>, ListItem)> {
         .on_click(|_, data, _| {
         data.0 = Some(data.1.index);
     })
-}
\ No newline at end of file
+}
@@ -1,10 +1,17 @@
 //! # An application to demonstrate data-validation
 //! There are no drop-down widgets implemented at this stage. A checkbox has been substituted.
 //! Custom widget functionality is explored elsewhere.
+//! Airports are entered as IATA codes and looked up in a small bundled table, departure and return
+//! times are entered in the local time of the airport the flight leaves from.
 
 use druid::{AppLauncher, WindowDesc, Widget, PlatformError, Data, Lens, Size,
-            Key, Color, WidgetExt, LocalizedString, Env, UpdateCtx, EventCtx, Event};
-use druid::widget::{TextBox, Flex, Checkbox, Button, Controller};
+            Key, Color, WidgetExt, LocalizedString, Env, UpdateCtx, EventCtx, Event,
+            AppDelegate, DelegateCtx, Target, Command, Selector, WindowId};
+use druid::widget::{TextBox, Flex, Checkbox, Button, Controller, Label, Align};
+
+use crate::menus::{self, MenuState, TaskMenu, ViewItem};
+use crate::persist::{self, Persist};
+use crate::dates;
 
 
 /// ## ENV Keys
@@ -14,20 +21,25 @@ const TXT_CLR_INVALID: Key<Color> = Key::new("app.txt.clr.invalid");
 const BTN_CLR_DISABLED: Key<Color> = Key::new("app.btn.clr.disabled");
 
 /// ## Constants
-const WINDOW_SIZE: Size = Size::new(250., 225.);
+const WINDOW_SIZE: Size = Size::new(340., 400.);
 const SPACING: f64 = 15.;
+const CODE_WIDTH: f64 = 60.;
+
+/// ## Commands from the View menu
+const TOGGLE_RETURN: Selector = Selector::new("app.flights.toggle-return");
 
 /// ## Entry Point
 pub fn main() -> Result<(), PlatformError> {
     // model data
-    let data = AppData::new();
+    let data = persist::restore().unwrap_or_else(AppData::new);
 
     // create the window and ui
     let window = WindowDesc::new(build_ui)
         .window_size(WINDOW_SIZE)
         .title(LocalizedString::new("multiwin-demo-window-title")
             .with_placeholder("Flight booker"))
-        .resizable(false);
+        .resizable(false)
+        .menu(menus::menu_bar(&data.menu_state()));
 
     // link ui and data starts loop
     AppLauncher::with_window(window)
@@ -36,35 +48,36 @@ pub fn main() -> Result<(), PlatformError> {
             env.set(TXT_CLR_INVALID, Color::rgb(0.85, 0.05, 0.1));
             env.set(BTN_CLR_DISABLED, Color::grey(0.5));
         })
+        .delegate(Delegate)
         .launch(data)?;
     Ok(())
 }
 
 /// ## Builder
 fn build_ui() -> impl Widget<AppData> {
+    let tbox_origin = TextBox::new()
+        .fix_width(CODE_WIDTH)
+        .lens(AppData::origin)
+        .controller(TboxControl)
+        .env_scope(|env, data: &AppData| scope_state(env, &data.origin_state));
+
+    let tbox_destination = TextBox::new()
+        .fix_width(CODE_WIDTH)
+        .lens(AppData::destination)
+        .controller(TboxControl)
+        .env_scope(|env, data: &AppData| scope_state(env, &data.destination_state));
+
     let tbox_out = TextBox::new()
         .expand_width()
         .lens(AppData::out_flight)
         .controller(TboxControl)
-        .env_scope(|env,data: &AppData| {
-            match data.out_state {
-                TboxState::Standard => env.set(druid::theme::LABEL_COLOR, env.get(druid::theme::LABEL_COLOR)),
-                TboxState::Invalid => env.set(druid::theme::LABEL_COLOR, env.get(TXT_CLR_INVALID)),
-                TboxState::Disabled => env.set(druid::theme::LABEL_COLOR, env.get(BTN_CLR_DISABLED)),
-            }
-        });
+        .env_scope(|env, data: &AppData| scope_state(env, &data.out_state));
 
     let tbox_return = TextBox::new()
         .expand_width()
         .lens(AppData::in_flight)
         .controller(TboxControl)
-        .env_scope(|env,data: &AppData| {
-            match data.in_state {
-                TboxState::Standard => env.set(druid::theme::LABEL_COLOR, env.get(druid::theme::LABEL_COLOR)),
-                TboxState::Invalid => env.set(druid::theme::LABEL_COLOR, env.get(TXT_CLR_INVALID)),
-                TboxState::Disabled => env.set(druid::theme::LABEL_COLOR, env.get(BTN_CLR_DISABLED)),
-            }
-        });
+        .env_scope(|env, data: &AppData| scope_state(env, &data.in_state));
 
     let btn_book = Button::new("Book")
         .expand_width()
@@ -88,16 +101,72 @@ fn build_ui() -> impl Widget<AppData> {
         .lens(AppData::return_flight)
         .controller(CBoxController);
 
+    let airports = Flex::row()
+        .with_child(Label::new("From: "))
+        .with_child(tbox_origin)
+        .with_spacer(SPACING)
+        .with_child(Label::new("To: "))
+        .with_child(tbox_destination);
+
+    // The summary lines show the UTC equivalent of each local time and the length of the trip
+    let summary = Flex::column()
+        .with_child(Align::left(Label::new(|data: &AppData, _: &_| {
+            match data.departure() {
+                Some(t) => format!("Depart: {}", t.utc()),
+                None => "Depart: -".to_string(),
+            }
+        })))
+        .with_child(Align::left(Label::new(|data: &AppData, _: &_| {
+            match (data.return_flight, data.return_departure()) {
+                (true, Some(t)) => format!("Return: {}", t.utc()),
+                (true, None) => "Return: -".to_string(),
+                (false, _) => "One-way".to_string(),
+            }
+        })))
+        .with_child(Align::left(Label::new(|data: &AppData, _: &_| {
+            match data.trip_length() {
+                Some(minutes) => format!("Trip length: {}", format_duration(minutes)),
+                None => "".to_string(),
+            }
+        })));
 
     Flex::column()
         .with_child(chk_box)
         .with_spacer(SPACING)
+        .with_child(airports)
+        .with_spacer(SPACING)
+        .with_child(Align::left(Label::new(|data: &AppData, _: &_| {
+            format!("Depart ({}):", zone_label(&data.origin))
+        })))
         .with_child(tbox_out)
         .with_spacer(SPACING)
+        .with_child(Align::left(Label::new(|data: &AppData, _: &_| {
+            format!("Return ({}):", zone_label(&data.destination))
+        })))
         .with_child(tbox_return)
+        .with_spacer(SPACING)
+        .with_child(summary)
         .with_flex_spacer(1.)
         .with_child(btn_book)
         .padding(SPACING)
+        .controller(menus::MenuBar)
+}
+
+/// Sets the text colour of a textbox from its state
+fn scope_state(env: &mut Env, state: &TboxState) {
+    match state {
+        TboxState::Standard => env.set(druid::theme::LABEL_COLOR, env.get(druid::theme::LABEL_COLOR)),
+        TboxState::Invalid => env.set(druid::theme::LABEL_COLOR, env.get(TXT_CLR_INVALID)),
+        TboxState::Disabled => env.set(druid::theme::LABEL_COLOR, env.get(BTN_CLR_DISABLED)),
+    }
+}
+
+/// The airport name and offset for a field label, or a prompt if the code is unknown
+fn zone_label(code: &str) -> String {
+    match Airport::find(code) {
+        Some(airport) => format!("{}, {}", airport.city, format_offset(airport.utc_offset)),
+        None => "unknown airport".to_string(),
+    }
 }
 
 #[derive(Clone, Data, PartialEq, Debug)]
@@ -112,8 +181,12 @@ enum TboxState {
 #[derive(Clone, Data, Lens, Debug)]
 struct AppData {
     return_flight: bool,
+    origin: String,
+    destination: String,
     out_flight: String,
     in_flight: String,
+    origin_state: TboxState,
+    destination_state: TboxState,
     out_state: TboxState,
     in_state: TboxState,
 }
@@ -123,43 +196,88 @@ impl AppData {
 
         AppData {
             return_flight: false,
-            out_flight: "27.03.2021".into(),
-            in_flight: "14.04.2021".into(),
+            origin: "LHR".into(),
+            destination: "JFK".into(),
+            out_flight: "27.03.2021 09:30".into(),
+            in_flight: "14.04.2021 18:00".into(),
+            origin_state: TboxState::Standard,
+            destination_state: TboxState::Standard,
             out_state: TboxState::Standard,
             in_state: TboxState::Disabled,
         }
     }
 
     fn btn_valid(&self) -> bool {
+        let airports_valid = self.origin_state == TboxState::Standard
+            && self.destination_state == TboxState::Standard;
+
         if self.return_flight {
-            self.in_state == TboxState::Standard && self.out_state == TboxState::Standard
+            airports_valid && self.in_state == TboxState::Standard && self.out_state == TboxState::Standard
         } else {
-            self.out_state == TboxState::Standard
+            airports_valid && self.out_state == TboxState::Standard
         }
     }
 
+    /// The outbound flight leaves the origin, so it is read in the origin's time zone
+    fn departure(&self) -> Option<ZonedDateTime> {
+        let airport = Airport::find(&self.origin)?;
+        let local = DateTime::from_str(self.out_flight.as_str()).ok()?;
+        Some(ZonedDateTime::new(local, airport.utc_offset))
+    }
+
+    /// The return flight leaves the destination, so it is read in the destination's time zone
+    fn return_departure(&self) -> Option<ZonedDateTime> {
+        let airport = Airport::find(&self.destination)?;
+        let local = DateTime::from_str(self.in_flight.as_str()).ok()?;
+        Some(ZonedDateTime::new(local, airport.utc_offset))
+    }
+
+    /// Minutes between the outbound and return departures, measured in UTC.
+    /// None when the return leaves before the outbound flight, there is no trip to measure
+    fn trip_length(&self) -> Option<i64> {
+        if !self.return_flight {
+            return None;
+        }
+        let out = self.departure()?;
+        let back = self.return_departure()?;
+        Some(back.utc_minutes() - out.utc_minutes()).filter(|&length| length > 0)
+    }
+
     // To update the text box states based on their inputs
     fn update_states(&mut self) {
-        let out_date = Date::from_str(self.out_flight.as_str());
-        let in_date = Date::from_str(self.in_flight.as_str());
+        let origin = Airport::find(&self.origin);
+        let destination = Airport::find(&self.destination);
+
+        self.origin_state = match origin {
+            Some(_) => TboxState::Standard,
+            None => TboxState::Invalid,
+        };
+
+        // flying to the airport you left from is not a trip
+        self.destination_state = match (origin, destination) {
+            (Some(a), Some(b)) if a.code == b.code => TboxState::Invalid,
+            (_, Some(_)) => TboxState::Standard,
+            (_, None) => TboxState::Invalid,
+        };
 
         // out flight must either be standard or invalid
-        match out_date {
-            Ok(_) => {
-                self.out_state = TboxState::Standard;
-            },
+        match DateTime::from_str(self.out_flight.as_str()) {
+            Ok(_) => self.out_state = TboxState::Standard,
             Err(_) => self.out_state = TboxState::Invalid,
         }
 
         if self.return_flight {
-            match in_date {
-                Ok(date) => {
-                    if out_date.is_ok() && date.is_before(&out_date.unwrap()) {
+            match DateTime::from_str(self.in_flight.as_str()) {
+                Ok(_) => {
+                    // comparing in UTC, an evening return west can be "earlier" locally than it is
+                    if let (Some(out), Some(back)) = (self.departure(), self.return_departure()) {
+                        if back.utc_minutes() <= out.utc_minutes() {
                             self.in_state = TboxState::Invalid;
-                    } else {
-                        // we don't want to throw an error here if out flight is invalid
-                        self.in_state = TboxState::Standard;
+                            return;
+                        }
                     }
+                    // we don't want to throw an error here if out flight is invalid
+                    self.in_state = TboxState::Standard;
                 },
                 Err(_) => self.in_state = TboxState::Invalid,
             }
@@ -236,20 +354,181 @@ impl <W: Widget<AppData>> Controller<AppData, W> for TboxControl {
     }
 }
 
-/// ## Application Logic
+/// The booking on a single line: return flight, origin, destination, departure and return
+/// separated by tabs. The field states are worked out again when it is read
+impl Persist for AppData {
+    const FILE: &'static str = "flights.tsv";
+    const VERSION: u32 = 1;
+
+    fn to_save(&self) -> String {
+        let fields = [&self.origin, &self.destination, &self.out_flight, &self.in_flight];
+        let mut tsv = self.return_flight.to_string();
+        for field in fields.iter() {
+            tsv.push('\t');
+            tsv.push_str(&field.replace(['\t', '\n'], " "));
+        }
+        tsv + "\n"
+    }
 
-fn submit(data: &mut AppData) {
+    fn from_save(text: &str, _version: u32) -> Option<Self> {
+        let fields: Vec<&str> = text.lines().next()?.split('\t').collect();
+        match fields.as_slice() {
+            [return_flight, origin, destination, out_flight, in_flight] => {
+                let mut data = AppData {
+                    return_flight: return_flight.parse().ok()?,
+                    origin: origin.to_string(),
+                    destination: destination.to_string(),
+                    out_flight: out_flight.to_string(),
+                    in_flight: in_flight.to_string(),
+                    ..AppData::new()
+                };
+                data.update_states();
+                Some(data)
+            },
+            _ => None,
+        }
+    }
+}
 
-    let out_flight = Date::from_str(data.out_flight.as_str());
+/// New clears the booking back to the example, the return flight can be switched from View
+impl TaskMenu for AppData {
+    fn menu_state(&self) -> MenuState {
+        MenuState {
+            new: true,
+            cut: true,
+            copy: true,
+            paste: true,
+            view: vec![ViewItem::new("Return Flight", TOGGLE_RETURN).checked(self.return_flight)],
+            ..MenuState::default()
+        }
+    }
+}
+
+struct Delegate;
+
+impl AppDelegate<AppData> for Delegate {
+    fn command(
+        &mut self,
+        _ctx: &mut DelegateCtx,
+        _target: Target,
+        cmd: &Command,
+        data: &mut AppData,
+        _env: &Env,
+    ) -> bool {
+        if cmd.is(druid::commands::NEW_FILE) {
+            *data = AppData::new();
+            false
+        } else if cmd.is(TOGGLE_RETURN) {
+            data.return_flight = !data.return_flight;
+            data.update_states();
+            false
+        } else {
+            true
+        }
+    }
 
-    if data.return_flight {
-        let in_flight = Date::from_str(data.in_flight.as_str());
-        if in_flight.is_ok() && out_flight.is_ok() && out_flight.unwrap().is_before(&in_flight.unwrap()) {
-            println!("Return flight\nleave: {}\nreturn:{}\n", data.out_flight, data.in_flight);
+    /// The booking is saved when the window closes, so it is still there on the next run
+    fn window_removed(
+        &mut self,
+        _id: WindowId,
+        data: &mut AppData,
+        _env: &Env,
+        _ctx: &mut DelegateCtx,
+    ) {
+        if let Err(error) = persist::store(data) {
+            println!("Error: {}", error);
         }
-    } else if out_flight.is_ok() {
-            println!("One-way flight\nleave: {}\n", data.out_flight);
+    }
+}
+
+/// ## Application Logic
+
+fn submit(data: &mut AppData) {
+    if !data.btn_valid() {
+        return;
+    }
+
+    if let Some(out_flight) = data.departure() {
+        if data.return_flight {
+            if let (Some(in_flight), Some(length)) = (data.return_departure(), data.trip_length()) {
+                println!("Return flight {} - {}\nleave: {} ({})\nreturn: {} ({})\ntrip length: {}\n",
+                         data.origin.trim().to_uppercase(), data.destination.trim().to_uppercase(),
+                         out_flight, out_flight.utc(), in_flight, in_flight.utc(), format_duration(length));
+            }
+        } else {
+            println!("One-way flight {} - {}\nleave: {} ({})\n",
+                     data.origin.trim().to_uppercase(), data.destination.trim().to_uppercase(),
+                     out_flight, out_flight.utc());
         }
+    }
+}
+
+/// Formats a number of minutes as days, hours and minutes
+fn format_duration(minutes: i64) -> String {
+    let days = minutes / (24 * 60);
+    let hours = (minutes % (24 * 60)) / 60;
+    let mins = minutes % 60;
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, mins)
+    } else {
+        format!("{}h {}m", hours, mins)
+    }
+}
+
+/// Formats an offset in minutes as UTC+hh:mm
+fn format_offset(offset: i16) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+    format!("UTC{}{:02}:{:02}", sign, offset / 60, offset % 60)
+}
+
+/// ## Airport Table
+/// A small offline table of IATA codes and their standard UTC offsets in minutes.
+/// Daylight saving is not modelled, every airport keeps its standard offset all year.
+struct Airport {
+    code: &'static str,
+    city: &'static str,
+    utc_offset: i16,
+}
+
+const AIRPORTS: &[Airport] = &[
+    Airport { code: "AKL", city: "Auckland", utc_offset: 720 },
+    Airport { code: "AMS", city: "Amsterdam", utc_offset: 60 },
+    Airport { code: "ATL", city: "Atlanta", utc_offset: -300 },
+    Airport { code: "BKK", city: "Bangkok", utc_offset: 420 },
+    Airport { code: "BOM", city: "Mumbai", utc_offset: 330 },
+    Airport { code: "CDG", city: "Paris", utc_offset: 60 },
+    Airport { code: "DEL", city: "Delhi", utc_offset: 330 },
+    Airport { code: "DEN", city: "Denver", utc_offset: -420 },
+    Airport { code: "DXB", city: "Dubai", utc_offset: 240 },
+    Airport { code: "FRA", city: "Frankfurt", utc_offset: 60 },
+    Airport { code: "GRU", city: "Sao Paulo", utc_offset: -180 },
+    Airport { code: "HKG", city: "Hong Kong", utc_offset: 480 },
+    Airport { code: "HNL", city: "Honolulu", utc_offset: -600 },
+    Airport { code: "IST", city: "Istanbul", utc_offset: 180 },
+    Airport { code: "JFK", city: "New York", utc_offset: -300 },
+    Airport { code: "JNB", city: "Johannesburg", utc_offset: 120 },
+    Airport { code: "KTM", city: "Kathmandu", utc_offset: 345 },
+    Airport { code: "LAX", city: "Los Angeles", utc_offset: -480 },
+    Airport { code: "LHR", city: "London", utc_offset: 0 },
+    Airport { code: "MAD", city: "Madrid", utc_offset: 60 },
+    Airport { code: "MEX", city: "Mexico City", utc_offset: -360 },
+    Airport { code: "NRT", city: "Tokyo", utc_offset: 540 },
+    Airport { code: "ORD", city: "Chicago", utc_offset: -360 },
+    Airport { code: "PEK", city: "Beijing", utc_offset: 480 },
+    Airport { code: "SFO", city: "San Francisco", utc_offset: -480 },
+    Airport { code: "SIN", city: "Singapore", utc_offset: 480 },
+    Airport { code: "SYD", city: "Sydney", utc_offset: 600 },
+    Airport { code: "YYZ", city: "Toronto", utc_offset: -300 },
+    Airport { code: "ZRH", city: "Zurich", utc_offset: 60 },
+];
+
+impl Airport {
+    /// Case-insensitive lookup of an IATA code
+    fn find(code: &str) -> Option<&'static Airport> {
+        let code = code.trim();
+        AIRPORTS.iter().find(|a| a.code.eq_ignore_ascii_case(code))
+    }
 }
 
 /// could also implement a datetime library, but given our needs we will just make our own
@@ -278,6 +557,10 @@ impl Date {
             let month: u16 = v_month.unwrap().parse()?;
             let year: u16 = v_year.unwrap().parse()?;
 
+            if !(1..=12).contains(&month) || day < 1 || i64::from(day) > dates::days_in_month(i64::from(month), i64::from(year)) {
+                return Err("read error: Date does not exist".into());
+            }
+
             Ok(Date {
                 day,
                 month,
@@ -290,33 +573,170 @@ impl Date {
         }
     }
 
-    fn is_after(&self, other: &Date) -> bool {
-        if self.year > other.year {
-            true
-        } else if self.year < other.year {
-            false
-        } else if self.month > other.month {
-            true
-        } else if self.month < other.month {
-            false
-        } else { self.day > other.day }
+    /// Days since 01.01.1970
+    fn to_days(&self) -> i64 {
+        dates::days_from_civil(i64::from(self.year), i64::from(self.month), i64::from(self.day))
     }
 
-    fn is_before(&self, other: &Date) -> bool {
-        if self.is_equal(&other) {
-            false
-        } else {
-            !self.is_after(&other)
+    /// The inverse of to_days
+    fn from_days(days: i64) -> Date {
+        let (year, month, day) = dates::civil_from_days(days);
+        Date {
+            day: day as u16,
+            month: month as u16,
+            year: year as u16,
         }
     }
-
-    fn is_equal(&self, other: &Date) -> bool {
-        self.year == other.year && self.month == other.month && self.day == other.day
-    }
 }
 
 impl std::fmt::Display for Date {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}.{}.{}", self.day, self.month, self.year)
     }
-}
\ No newline at end of file
+}
+
+/// A local date with a time of day, entered as "dd.mm.yyyy hh:mm".
+/// The time may be left off, in which case midnight is assumed.
+struct DateTime {
+    date: Date,
+    minutes: u16,
+}
+
+impl DateTime {
+    fn from_str(input: &str) -> Result<DateTime, Box<dyn std::error::Error>> {
+        let mut parts = input.split_whitespace();
+        let date = Date::from_str(parts.next().unwrap_or(""))?;
+
+        let minutes = match parts.next() {
+            Some(time) => {
+                let (hour, minute) = match time.find(':') {
+                    Some(i) => (&time[..i], &time[i + 1..]),
+                    None => return Err("read error: Enter time as hh:mm".into()),
+                };
+                let hour: u16 = hour.parse()?;
+                let minute: u16 = minute.parse()?;
+                if hour > 23 || minute > 59 {
+                    return Err("read error: Time does not exist".into());
+                }
+                hour * 60 + minute
+            },
+            None => 0,
+        };
+
+        if parts.next().is_some() {
+            return Err("read error: Unexpected input after time".into());
+        }
+
+        Ok(DateTime { date, minutes })
+    }
+
+    fn from_minutes(total: i64) -> DateTime {
+        DateTime {
+            date: Date::from_days(total.div_euclid(24 * 60)),
+            minutes: total.rem_euclid(24 * 60) as u16,
+        }
+    }
+
+    /// Minutes since 01.01.1970 00:00 in whatever zone this time was entered in
+    fn to_minutes(&self) -> i64 {
+        self.date.to_days() * 24 * 60 + i64::from(self.minutes)
+    }
+}
+
+impl std::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {:02}:{:02}", self.date, self.minutes / 60, self.minutes % 60)
+    }
+}
+
+/// A local date-time paired with the UTC offset it was entered in
+struct ZonedDateTime {
+    local: DateTime,
+    utc_offset: i16,
+}
+
+impl ZonedDateTime {
+    fn new(local: DateTime, utc_offset: i16) -> Self {
+        ZonedDateTime { local, utc_offset }
+    }
+
+    fn utc_minutes(&self) -> i64 {
+        self.local.to_minutes() - i64::from(self.utc_offset)
+    }
+
+    /// The same instant written in UTC
+    fn utc(&self) -> String {
+        format!("{} UTC", DateTime::from_minutes(self.utc_minutes()))
+    }
+}
+
+impl std::fmt::Display for ZonedDateTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.local, format_offset(self.utc_offset))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> Date {
+        Date::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn days_count_from_the_epoch() {
+        assert_eq!(date("01.01.1970").to_days(), 0);
+        assert_eq!(date("31.12.1969").to_days(), -1);
+        assert_eq!(date("01.03.2000").to_days() - date("28.02.2000").to_days(), 2);
+        assert_eq!(date("01.03.1900").to_days() - date("28.02.1900").to_days(), 1);
+        assert_eq!(date("01.01.2025").to_days() - date("01.01.2024").to_days(), 366);
+        assert_eq!(Date::from_days(11_016).to_string(), "29.2.2000");
+
+        for days in (-700_000..2_000_000).step_by(13) {
+            assert_eq!(Date::from_days(days).to_days(), days);
+        }
+    }
+
+    #[test]
+    fn leap_days_follow_the_century_rule() {
+        assert!(Date::from_str("29.02.2024").is_ok());
+        assert!(Date::from_str("29.02.2000").is_ok());
+        assert!(Date::from_str("29.02.2023").is_err());
+        assert!(Date::from_str("29.02.1900").is_err());
+    }
+
+    #[test]
+    fn dates_and_times_that_dont_exist_are_rejected() {
+        assert!(DateTime::from_str("30.04.2021 10:00").is_ok());
+        assert!(DateTime::from_str("31.04.2021 10:00").is_err());
+        assert!(DateTime::from_str("31.04.2021").is_err());
+        assert!(DateTime::from_str("30.04.2021 24:00").is_err());
+        assert!(DateTime::from_str("30.04.2021 10:00 pm").is_err());
+        assert_eq!(DateTime::from_str("30.04.2021").unwrap().to_string(), "30.4.2021 00:00");
+    }
+
+    #[test]
+    fn trips_are_measured_across_time_zones() {
+        let mut data = AppData::new();
+        data.return_flight = true;
+        data.origin = "LHR".into();
+        data.destination = "JFK".into();
+        data.out_flight = "27.03.2021 09:30".into();
+        // 09:00 in New York is 14:00 in London
+        data.in_flight = "27.03.2021 09:00".into();
+        assert_eq!(data.trip_length(), Some(270));
+        assert_eq!(data.return_departure().unwrap().utc(), "27.3.2021 14:00 UTC");
+
+        // later on the clock, but it leaves London before the outbound left New York
+        data.origin = "JFK".into();
+        data.destination = "LHR".into();
+        data.in_flight = "27.03.2021 12:00".into();
+        assert_eq!(data.trip_length(), None);
+
+        data.return_flight = false;
+        data.in_flight = "28.03.2021 12:00".into();
+        assert_eq!(data.trip_length(), None);
+    }
+}
@@ -9,6 +9,10 @@ mod flights;
 mod timer;
 mod crud;
 mod circles;
+mod units;
+mod persist;
+mod dates;
+mod menus;
 
 use std::io;
 
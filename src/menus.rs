@@ -0,0 +1,160 @@
+//! # The window menu bar shared by the tasks
+//! Every task has the same File and Edit menus, so the items and their shortcuts are the same
+//! everywhere, and a View menu for its own items when it has any.
+//! The items send the standard druid commands, a task handles the ones it supports and reports
+//! through `MenuState` which of them can be used. The menu is rebuilt when that changes.
+
+use druid::{Data, Env, FileDialogOptions, FileSpec, LocalizedString, MenuDesc, MenuItem, SysMods,
+            UpdateCtx, Widget, Command};
+use druid::commands;
+use druid::platform_menus::common;
+use druid::widget::Controller;
+
+/// What the menu items can do at the moment, anything a task doesn't support is left false
+#[derive(Clone, PartialEq, Default)]
+pub struct MenuState {
+    pub new: bool,
+    pub open: bool,
+    pub save: bool,
+    // the kind of file Open and Save work with, they are greyed out without one
+    pub file: Option<FileSpec>,
+    pub undo: bool,
+    pub redo: bool,
+    pub cut: bool,
+    pub copy: bool,
+    pub paste: bool,
+    pub view: Vec<ViewItem>,
+}
+
+/// An item in a task's View menu
+#[derive(Clone)]
+pub struct ViewItem {
+    name: &'static str,
+    command: Command,
+    // pressed along with Ctrl, or Cmd on macOS
+    key: Option<&'static str>,
+    // a setting that is on or off shows a tick, None for an item that just does something
+    checked: Option<bool>,
+    enabled: bool,
+    // starts a new group of items
+    separated: bool,
+}
+
+impl ViewItem {
+    pub fn new(name: &'static str, command: impl Into<Command>) -> Self {
+        ViewItem {
+            name,
+            command: command.into(),
+            key: None,
+            checked: None,
+            enabled: true,
+            separated: false,
+        }
+    }
+
+    pub fn key(mut self, key: &'static str) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn checked(mut self, checked: bool) -> Self {
+        self.checked = Some(checked);
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn separated(mut self) -> Self {
+        self.separated = true;
+        self
+    }
+}
+
+/// Commands can't be compared, the name stands in for the command as each names one item
+impl PartialEq for ViewItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.key == other.key
+            && self.checked == other.checked
+            && self.enabled == other.enabled
+            && self.separated == other.separated
+    }
+}
+
+/// Implemented by the data of each task that has a menu bar
+pub trait TaskMenu: Data {
+    fn menu_state(&self) -> MenuState;
+}
+
+/// The File, Edit and View menus for a state
+pub fn menu_bar<T: Data>(state: &MenuState) -> MenuDesc<T> {
+    let file_options = || {
+        let options = FileDialogOptions::new();
+        match state.file {
+            Some(file) => options.allowed_types(vec![file]).default_type(file),
+            None => options,
+        }
+    };
+    let (open, save) = (state.open && state.file.is_some(), state.save && state.file.is_some());
+    let (new, undo, redo) = (state.new, state.undo, state.redo);
+    let (cut, copy, paste) = (state.cut, state.copy, state.paste);
+
+    // quitting closes the windows rather than stopping the app outright,
+    // so the tasks save their state as they would when the window is closed
+    let file = MenuDesc::new(LocalizedString::new("File"))
+        .append(MenuItem::new(LocalizedString::new("New"), commands::NEW_FILE)
+            .hotkey(SysMods::Cmd, "n")
+            .disabled_if(|| !new))
+        .append(MenuItem::new(LocalizedString::new("Open…"), commands::SHOW_OPEN_PANEL.with(file_options()))
+            .hotkey(SysMods::Cmd, "o")
+            .disabled_if(|| !open))
+        .append(MenuItem::new(LocalizedString::new("Save…"), commands::SHOW_SAVE_PANEL.with(file_options()))
+            .hotkey(SysMods::Cmd, "s")
+            .disabled_if(|| !save))
+        .append_separator()
+        .append(MenuItem::new(LocalizedString::new("Quit"), commands::CLOSE_ALL_WINDOWS)
+            .hotkey(SysMods::Cmd, "q"));
+
+    let edit = MenuDesc::new(LocalizedString::new("Edit"))
+        .append(common::undo().disabled_if(|| !undo))
+        .append(common::redo().disabled_if(|| !redo))
+        .append_separator()
+        .append(common::cut().disabled_if(|| !cut))
+        .append(common::copy().disabled_if(|| !copy))
+        .append(common::paste().disabled_if(|| !paste));
+
+    let menu = MenuDesc::empty().append(file).append(edit);
+    if state.view.is_empty() {
+        return menu;
+    }
+    let view = state.view.iter().fold(MenuDesc::new(LocalizedString::new("View")), |mut view, item| {
+        if item.separated {
+            view = view.append_separator();
+        }
+        let mut entry = MenuItem::new(LocalizedString::new(item.name), item.command.clone());
+        if let Some(key) = item.key {
+            entry = entry.hotkey(SysMods::Cmd, key);
+        }
+        let (checked, enabled) = (item.checked == Some(true), item.enabled);
+        view.append(entry.selected_if(|| checked).disabled_if(|| !enabled))
+    });
+    menu.append(view)
+}
+
+/// Goes around the root widget of a task and rebuilds the menu bar when its state changes
+pub struct MenuBar;
+
+impl <T: TaskMenu, W: Widget<T>> Controller<T, W> for MenuBar {
+    fn update(&mut self, child: &mut W, ctx: &mut UpdateCtx, old_data: &T, data: &T, env: &Env) {
+        if !old_data.same(data) {
+            let state = data.menu_state();
+            if state != old_data.menu_state() {
+                ctx.set_menu(menu_bar::<T>(&state));
+            }
+        }
+        child.update(ctx, old_data, data, env)
+    }
+}
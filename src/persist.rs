@@ -0,0 +1,188 @@
+//! # Saving app state between runs
+//! Files are kept in the XDG data directory, `$XDG_DATA_HOME/druid-7guis` or
+//! `~/.local/share/druid-7guis` when it is not set.
+//! Each task implements `Persist` to turn its state into text and back, this adds a header line
+//! with the version of the format so older saves can still be read after the format changes.
+//! A file that can't be read is moved aside rather than overwritten on the next save,
+//! and the task starts fresh.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const APP_DIR: &str = "druid-7guis";
+/// Starts the first line of every save, followed by the version of the format
+const HEADER: &str = "druid-7guis version ";
+/// Added to the name of a file that couldn't be read when it is moved aside
+const UNREADABLE: &str = "unreadable";
+
+/// Implemented by the state of each task that is kept between runs
+pub trait Persist: Sized {
+    /// The name of the file in the data directory
+    const FILE: &'static str;
+    /// Raised whenever the format changes, `from_save` is told which version it is reading
+    const VERSION: u32;
+
+    /// The state as text in the current format
+    fn to_save(&self) -> String;
+
+    /// Reads text saved by any version up to the current one, None if it can't be read.
+    /// Files saved before there were versions are version 0
+    fn from_save(text: &str, version: u32) -> Option<Self>;
+}
+
+/// The directory app state is saved in, None if no home directory can be found
+pub fn data_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+            PathBuf::from(home).join(".local").join("share")
+        }
+    };
+    Some(base.join(APP_DIR))
+}
+
+/// The state saved on the last run, None if there is none or it can't be read.
+/// Problems are reported and an unreadable file is moved aside
+pub fn restore<T: Persist>() -> Option<T> {
+    restore_from(&data_dir()?)
+}
+
+/// Saves the state, replacing any previous save
+pub fn store<T: Persist>(state: &T) -> io::Result<()> {
+    let dir = data_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+    store_in(&dir, state)
+}
+
+fn restore_from<T: Persist>(dir: &Path) -> Option<T> {
+    let path = dir.join(T::FILE);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return None,
+        Err(error) => {
+            println!("Error: {}: {}", path.display(), error);
+            return set_aside(&path);
+        }
+    };
+
+    let (version, body) = split_header(&text);
+    match version {
+        Some(version) if version > T::VERSION => {
+            println!("Error: {} was saved by a newer version", path.display());
+            set_aside(&path)
+        },
+        Some(version) => T::from_save(body, version).or_else(|| {
+            println!("Error: {} could not be read", path.display());
+            set_aside(&path)
+        }),
+        None => {
+            println!("Error: {} has an unknown version", path.display());
+            set_aside(&path)
+        },
+    }
+}
+
+/// The contents are written next to the target first and then moved over it,
+/// so a crash part way through never leaves a half written file behind
+fn store_in<T: Persist>(dir: &Path, state: &T) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let path = dir.join(T::FILE);
+    let temp = dir.join(format!("{}.tmp", T::FILE));
+    fs::write(&temp, format!("{}{}\n{}", HEADER, T::VERSION, state.to_save()))?;
+    fs::rename(&temp, &path)
+}
+
+/// The version in the header and the text after it.
+/// Text without a header is version 0, the version is None if the header can't be read
+fn split_header(text: &str) -> (Option<u32>, &str) {
+    match text.strip_prefix(HEADER) {
+        Some(rest) => {
+            let (version, body) = rest.split_once('\n').unwrap_or((rest, ""));
+            (version.trim().parse().ok(), body)
+        },
+        None => (Some(0), text),
+    }
+}
+
+/// Renames a file that couldn't be read so the next save doesn't lose it, always None
+fn set_aside<T>(path: &Path) -> Option<T> {
+    let aside = path.with_extension(match path.extension() {
+        Some(ext) => format!("{}.{}", ext.to_string_lossy(), UNREADABLE),
+        None => UNREADABLE.to_string(),
+    });
+    match fs::rename(path, &aside) {
+        Ok(()) => println!("Starting afresh, the old file is kept at {}", aside.display()),
+        Err(error) => println!("Error: {}", error),
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Numbers one per line, version 0 had them comma separated
+    #[derive(Debug, PartialEq)]
+    struct Numbers(Vec<i32>);
+
+    impl Persist for Numbers {
+        const FILE: &'static str = "numbers.txt";
+        const VERSION: u32 = 1;
+
+        fn to_save(&self) -> String {
+            self.0.iter().map(|n| format!("{}\n", n)).collect()
+        }
+
+        fn from_save(text: &str, version: u32) -> Option<Self> {
+            let numbers: Option<Vec<i32>> = match version {
+                0 => text.trim().split(',').map(|n| n.trim().parse().ok()).collect(),
+                _ => text.lines().map(|n| n.parse().ok()).collect(),
+            };
+            numbers.map(Numbers)
+        }
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-test-{}-{}", APP_DIR, name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn saves_round_trip_and_old_versions_are_read() {
+        let dir = test_dir("versions");
+        assert_eq!(restore_from::<Numbers>(&dir), None);
+
+        store_in(&dir, &Numbers(vec![1, -2, 3])).unwrap();
+        let text = fs::read_to_string(dir.join(Numbers::FILE)).unwrap();
+        assert_eq!(text, "druid-7guis version 1\n1\n-2\n3\n");
+        assert_eq!(restore_from(&dir), Some(Numbers(vec![1, -2, 3])));
+
+        fs::write(dir.join(Numbers::FILE), "4, 5").unwrap();
+        assert_eq!(restore_from(&dir), Some(Numbers(vec![4, 5])));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_saves_are_moved_aside() {
+        let dir = test_dir("unreadable");
+        let aside = dir.join("numbers.txt.unreadable");
+        fs::create_dir_all(&dir).unwrap();
+
+        for text in ["druid-7guis version 1\n1\ntwo\n", "druid-7guis version 2\n1\n", "druid-7guis version ?\n"] {
+            fs::write(dir.join(Numbers::FILE), text).unwrap();
+            assert_eq!(restore_from::<Numbers>(&dir), None);
+            assert!(!dir.join(Numbers::FILE).exists());
+            assert_eq!(fs::read_to_string(&aside).unwrap(), text);
+        }
+
+        // starting afresh, the next save doesn't touch the file set aside
+        store_in(&dir, &Numbers(vec![])).unwrap();
+        assert_eq!(restore_from(&dir), Some(Numbers(vec![])));
+        assert!(aside.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
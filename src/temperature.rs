@@ -1,69 +1,610 @@
 //! # An app for converting Celsius to Farenheight and back, to show basic Lenses and controllers
+//! The converter is built on the unit registry in `units`, temperature is the preset this task
+//! opens with but any category can be picked and every unit in it is linked to every other.
+//! Conversions can be kept in a history, pinned as favourites and exported as CSV.
 
 use druid::{AppLauncher, WindowDesc, Widget, PlatformError,
-            widget::{Label, TextBox, Flex, Align, Controller},
-            Data, Lens, Size, WidgetExt, Event, EventCtx, Env
+            widget::{Label, TextBox, Flex, Align, Controller, RadioGroup, List, Scroll, Stepper, Button},
+            Data, Lens, Size, WidgetExt, Event, EventCtx, Env, Key, Color, KeyCode, Selector, Target,
+            AppDelegate, DelegateCtx, Command, Application, FileDialogOptions, FileSpec, WindowId
 };
+use druid::im::Vector;
 
+use crate::units::{self, CATEGORIES};
+use crate::menus::{self, MenuState, TaskMenu, ViewItem};
+use crate::persist::{self, Persist};
 
-const WINDOW_TITLE: &str = "Temperature Converter";
-const WINDOW_SIZE: Size = Size::new(350., 100.);
+
+/// ## ENV Keys
+const TXT_CLR_INVALID: Key<Color> = Key::new("app.txt.clr.invalid");
+
+const WINDOW_SIZE: Size = Size::new(900., 420.);
+const PADDING: f64 = 8.;
+const TBOX_WIDTH: f64 = 150.;
+const HISTORY_WIDTH: f64 = 300.;
+const FAVOURITES_HEIGHT: f64 = 100.;
+const MAX_PRECISION: f64 = 10.;
+const MAX_HISTORY: usize = 50;
+
+const CSV_FILE: FileSpec = FileSpec::new("CSV", &["csv"]);
 
 pub fn main() -> Result<(), PlatformError> {
     // model data
-    let data = AppData::new();
+    let data = persist::restore().unwrap_or_else(|| AppData::new(units::TEMPERATURE, Locale::from_env()));
 
     // create the window and ui
     let window = WindowDesc::new(build_ui)
         .window_size(WINDOW_SIZE)
-        .title(WINDOW_TITLE)
-        .resizable(false);
+        .title(|data: &AppData, _: &Env| format!("{} Converter", CATEGORIES[data.category].name))
+        .resizable(false)
+        .menu(menus::menu_bar(&data.menu_state()));
 
     // link ui and data starts loop
-    AppLauncher::with_window(window).launch(data)?;
+    AppLauncher::with_window(window)
+        .configure_env(|env, _state| {
+            env.set(TXT_CLR_INVALID, Color::rgb(0.85, 0.05, 0.1));
+        })
+        .delegate(Delegate)
+        .launch(data)?;
     Ok(())
 }
 
 // define UI
 fn build_ui() -> impl Widget<AppData> {
 
+    let categories = RadioGroup::new(
+        CATEGORIES.iter().enumerate().map(|(i, c)| (c.name, i))
+    )
+        .lens(AppData::category);
+
     // the only way to update the text is to update the model
-    let tbox_f = TextBox::new()
-        .lens(AppData::tbox_f)
-        .controller(FController);
+    let fields = Scroll::new(List::new(|| {
+        Flex::row()
+            .with_child(TextBox::new()
+                .fix_width(TBOX_WIDTH)
+                .lens(UnitField::text)
+                // highlight the source field when it can't be read, the same way flights does
+                .env_scope(|env, field: &UnitField| {
+                    if field.invalid {
+                        env.set(druid::theme::LABEL_COLOR, env.get(TXT_CLR_INVALID));
+                    }
+                }))
+            .with_spacer(PADDING)
+            .with_child(Label::new(|field: &UnitField, _: &_| {
+                let unit = field.unit();
+                format!("{}  {}", unit.symbol, unit.name)
+            }))
+            .padding((0., 2.))
+    }))
+        .vertical()
+        .lens(AppData::fields);
+
+    let status = Label::new(|data: &AppData, _: &_| {
+        match data.state {
+            InputState::Valid => "".to_string(),
+            InputState::Empty => "Enter a value".to_string(),
+            InputState::Invalid => format!("\"{}\" is not a number", data.source_text()),
+            InputState::BelowLimit => {
+                let unit = data.fields[data.last_edited].unit();
+                match &CATEGORIES[data.category].limit {
+                    Some(limit) => format!("Below {} ({} {})", limit.name,
+                                           format_value(limit.in_unit(unit), data.precision as usize, data.locale),
+                                           unit.symbol),
+                    None => "".to_string(),
+                }
+            },
+        }
+    });
+
+    let precision = Flex::row()
+        .with_child(Label::new("Precision: "))
+        .with_child(Stepper::new()
+            .with_range(0., MAX_PRECISION)
+            .with_step(1.)
+            .lens(AppData::precision))
+        .with_child(Label::new(|data: &AppData, _: &_| {
+            format!(" {} decimals", data.precision as usize)
+        }));
+
+    let locale = RadioGroup::new(vec![
+        ("1,234.5", Locale::Point),
+        ("1.234,5", Locale::Comma),
+    ])
+        .lens(AppData::locale);
+
+    let settings = Flex::column()
+        .with_child(categories)
+        .with_spacer(PADDING)
+        .with_child(Align::left(Label::new("Number format:")))
+        .with_child(locale)
+        .with_spacer(PADDING)
+        .with_child(precision);
 
-    let tbox_c = TextBox::new()
-        .lens(AppData::tbox_c)
-        .controller(CController);
+    let favourites = Scroll::new(List::new(history_row))
+        .vertical()
+        .lens(AppData::favourites)
+        .fix_height(FAVOURITES_HEIGHT);
+
+    let history = Scroll::new(List::new(history_row))
+        .vertical()
+        .lens(AppData::history);
+
+    let history_buttons = Flex::row()
+        .with_child(Button::new("Add").on_click(|_, data: &mut AppData, _| data.record()))
+        .with_spacer(PADDING)
+        .with_child(Button::new("Copy").on_click(|_, data: &mut AppData, _| {
+            Application::global().clipboard().put_string(data.history_csv());
+        }))
+        .with_spacer(PADDING)
+        .with_child(Button::new("Export").on_click(|ctx, _data: &mut AppData, _| {
+            let options = FileDialogOptions::new()
+                .allowed_types(vec![CSV_FILE])
+                .default_type(CSV_FILE);
+            ctx.submit_command(druid::commands::SHOW_SAVE_PANEL.with(options), None);
+        }))
+        .with_spacer(PADDING)
+        .with_child(Button::new("Clear").on_click(|_, data: &mut AppData, _| data.history.clear()));
+
+    let log = Flex::column()
+        .with_child(Align::left(Label::new("Favourites")))
+        .with_child(favourites)
+        .with_spacer(PADDING)
+        .with_child(Align::left(Label::new("History (Enter to add)")))
+        .with_flex_child(history, 1.)
+        .with_spacer(PADDING)
+        .with_child(history_buttons)
+        .fix_width(HISTORY_WIDTH);
 
     let layout = Flex::row()
-        .with_child(tbox_c)
-        .with_child(Label::new( "℃  =  "))
-        .with_child(tbox_f)
-        .with_child(Label::new( "℉ "));
+        .with_child(settings)
+        .with_spacer(PADDING * 2.)
+        .with_flex_child(Flex::column()
+                             .with_flex_child(fields, 1.)
+                             .with_spacer(PADDING)
+                             .with_child(Align::left(status)), 1.)
+        .with_spacer(PADDING * 2.)
+        .with_child(log)
+        .padding(PADDING * 2.)
+        .controller(ConvertController);
+
+    Align::centered(layout).controller(menus::MenuBar)
+}
+
+/// A history or favourites entry: clicking the text restores it, the star pins or unpins it
+fn history_row() -> impl Widget<HistoryEntry> {
+    Flex::row()
+        .with_child(Button::new(|entry: &HistoryEntry, _: &_| {
+            if entry.pinned { "★".to_string() } else { "☆".to_string() }
+        })
+            .on_click(|ctx, entry: &mut HistoryEntry, _| {
+                ctx.submit_command(TOGGLE_PIN.with(entry.id), Target::Global);
+            }))
+        .with_spacer(PADDING)
+        .with_flex_child(Label::new(|entry: &HistoryEntry, _: &_| entry.summary.clone())
+                             .on_click(|ctx, entry: &mut HistoryEntry, _| {
+                                 ctx.submit_command(RESTORE_ENTRY.with(entry.id), Target::Global);
+                             }), 1.)
+        .padding((0., 2.))
+}
+
+
+/// A text field for one unit of the current category
+#[derive(Clone, Data, Lens)]
+struct UnitField {
+    category: usize,
+    index: usize,
+    text: String,
+    invalid: bool,
+}
 
-    Align::centered(layout)
+impl UnitField {
+    fn unit(&self) -> &'static units::Unit {
+        units::unit(self.category, self.index)
+    }
 }
 
+/// Whether the text in the source field could be converted
+#[derive(Clone, Copy, Data, PartialEq, Debug)]
+enum InputState {
+    Valid,
+    Empty,
+    Invalid,
+    // converted, but below the physical limit of the category such as absolute zero
+    BelowLimit,
+}
+
+/// A conversion kept in the history, the value is stored so it can be restored or exported
+/// at full precision whatever format it was shown in
+#[derive(Clone, Data, Lens)]
+struct HistoryEntry {
+    id: u64,
+    category: usize,
+    unit: usize,
+    value: f64,
+    summary: String,
+    pinned: bool,
+}
+
+impl HistoryEntry {
+    /// Whether both convert the same value from the same unit, however they are shown.
+    /// Every other unit of the category is converted to, so this covers the whole conversion
+    fn same_conversion(&self, other: &HistoryEntry) -> bool {
+        (self.category, self.unit, self.value) == (other.category, other.unit, other.value)
+    }
+}
+
+/// The decimal and grouping separators numbers are read and written with
+#[derive(Clone, Copy, Data, PartialEq, Debug)]
+enum Locale {
+    /// 1,234.5
+    Point,
+    /// 1.234,5
+    Comma,
+}
+
+impl Locale {
+    /// Languages that write a decimal comma, as found at the start of LANG (e.g. de_DE.UTF-8)
+    const COMMA_LANGUAGES: &'static [&'static str] = &[
+        "bg", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "lt", "lv",
+        "nb", "nl", "nn", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+    ];
+
+    /// Picks the locale from the usual environment variables, falling back to a decimal point
+    fn from_env() -> Self {
+        let lang = ["LC_ALL", "LC_NUMERIC", "LANG"].iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|v| !v.is_empty())
+            .unwrap_or_default();
+        let language = lang.split(['_', '.', '-']).next().unwrap_or("");
+
+        if Locale::COMMA_LANGUAGES.contains(&language) {
+            Locale::Comma
+        } else {
+            Locale::Point
+        }
+    }
+
+    fn decimal(self) -> char {
+        match self {
+            Locale::Point => '.',
+            Locale::Comma => ',',
+        }
+    }
+
+    fn group(self) -> char {
+        match self {
+            Locale::Point => ',',
+            Locale::Comma => '.',
+        }
+    }
+}
 
 #[derive(Clone, Data, Lens)]
 struct AppData {
-    tbox_c: String,
-    tbox_f: String
+    category: usize,
+    fields: Vector<UnitField>,
+    // the field the user last edited, this is the source of every conversion
+    last_edited: usize,
+    // the last value read from the source field
+    value: f64,
+    state: InputState,
+    locale: Locale,
+    // decimals shown, f64 for the stepper
+    precision: f64,
+    // newest first
+    history: Vector<HistoryEntry>,
+    favourites: Vector<HistoryEntry>,
+    next_id: u64,
 }
 
 impl AppData {
-    fn new() -> AppData {
-        AppData {
-            tbox_c: "0".into(),
-            tbox_f: "32".into()
+    fn new(category: usize, locale: Locale) -> AppData {
+        let mut data = AppData {
+            category,
+            fields: Vector::new(),
+            last_edited: 0,
+            value: 0.,
+            state: InputState::Valid,
+            locale,
+            precision: 1.,
+            history: Vector::new(),
+            favourites: Vector::new(),
+            next_id: 0,
+        };
+        data.set_category(category);
+        data
+    }
+
+    /// Replaces the fields with the units of a category, starting from zero in its first unit
+    fn set_category(&mut self, category: usize) {
+        self.category = category;
+        self.fields = (0..CATEGORIES[category].units.len())
+            .map(|index| UnitField { category, index, text: "0".into(), invalid: false })
+            .collect();
+        self.last_edited = 0;
+        self.convert();
+    }
+
+    fn source_text(&self) -> &str {
+        &self.fields[self.last_edited].text
+    }
+
+    /// Converts from the last edited field into every other one.
+    /// When the source can't be read the other fields are cleared rather than left stale,
+    /// and the source is marked invalid so it can be highlighted.
+    fn convert(&mut self) {
+        let text = self.source_text().trim();
+        let value = if text.is_empty() {
+            self.state = InputState::Empty;
+            None
+        } else {
+            let value = parse_value(text, self.locale);
+            let unit = self.fields[self.last_edited].unit();
+            let limit = &CATEGORIES[self.category].limit;
+            self.state = match value {
+                Some(v) if limit.as_ref().is_some_and(|l| l.is_below(v, unit)) => InputState::BelowLimit,
+                Some(_) => InputState::Valid,
+                None => InputState::Invalid,
+            };
+            value
+        };
+
+        if let Some(v) = value {
+            self.value = v;
+        }
+
+        let source = self.last_edited;
+        let invalid = self.state == InputState::Invalid || self.state == InputState::BelowLimit;
+        for (i, field) in self.fields.iter_mut().enumerate() {
+            field.invalid = i == source && invalid;
+        }
+        self.show(value, false);
+    }
+
+    /// Writes the value, given in the unit of the source field, into the other fields,
+    /// and into the source as well when `source_too`. With no value they are cleared.
+    fn show(&mut self, value: Option<f64>, source_too: bool) {
+        let source = self.last_edited;
+        let from = self.fields[source].unit();
+        let precision = self.precision as usize;
+        let locale = self.locale;
+        for (i, field) in self.fields.iter_mut().enumerate() {
+            if i != source || source_too {
+                field.text = match value {
+                    Some(v) if i == source => format_value(v, precision, locale),
+                    Some(v) => format_value(units::convert(v, from, field.unit()), precision, locale),
+                    None => "".to_string(),
+                };
+            }
         }
     }
+
+    /// Rewrites every field, the source included, after the number format has changed.
+    /// The value stays as it was entered, so a lower precision only changes what is shown.
+    fn reformat(&mut self) {
+        if !self.has_value() {
+            return;
+        }
+        self.show(Some(self.value), true);
+        self.refresh_summaries();
+    }
+
+    fn has_value(&self) -> bool {
+        self.state == InputState::Valid || self.state == InputState::BelowLimit
+    }
+
+    /// Adds the current conversion to the top of the history, unless it is already there
+    fn record(&mut self) {
+        if !self.has_value() {
+            return;
+        }
+        let mut entry = HistoryEntry {
+            id: self.next_id,
+            category: self.category,
+            unit: self.last_edited,
+            value: self.value,
+            summary: String::new(),
+            pinned: false,
+        };
+        entry.summary = self.summary(&entry);
+
+        if self.history.front().is_some_and(|e| e.same_conversion(&entry)) {
+            return;
+        }
+        entry.pinned = self.favourites.iter().any(|f| f.same_conversion(&entry));
+
+        self.next_id += 1;
+        self.history.push_front(entry);
+        // im's truncate panics if the vector is already shorter
+        if self.history.len() > MAX_HISTORY {
+            self.history.truncate(MAX_HISTORY);
+        }
+    }
+
+    /// "21.5 ℃ = 70.7 ℉ = ..." in the current number format
+    fn summary(&self, entry: &HistoryEntry) -> String {
+        let category = &CATEGORIES[entry.category];
+        let from = &category.units[entry.unit];
+        category.units.iter()
+            .map(|to| format!("{} {}",
+                              format_value(units::convert(entry.value, from, to), self.precision as usize, self.locale),
+                              to.symbol))
+            .collect::<Vec<String>>()
+            .join(" = ")
+    }
+
+    /// Rewrites the history text after the number format has changed
+    fn refresh_summaries(&mut self) {
+        let history: Vector<HistoryEntry> = self.history.iter()
+            .map(|e| HistoryEntry { summary: self.summary(e), ..e.clone() })
+            .collect();
+        let favourites: Vector<HistoryEntry> = self.favourites.iter()
+            .map(|e| HistoryEntry { summary: self.summary(e), ..e.clone() })
+            .collect();
+        self.history = history;
+        self.favourites = favourites;
+    }
+
+    /// Pins a history entry to the favourites, or unpins it if it is already there
+    fn toggle_pin(&mut self, id: u64) {
+        let entry = match self.favourites.iter().chain(self.history.iter()).find(|e| e.id == id) {
+            Some(entry) => entry.clone(),
+            None => return,
+        };
+        // matched by conversion, the same conversion recorded again has an id of its own
+        let pinned = match self.favourites.iter().position(|f| f.same_conversion(&entry)) {
+            Some(i) => {
+                self.favourites.remove(i);
+                false
+            },
+            None => {
+                self.favourites.push_back(HistoryEntry { pinned: true, ..entry.clone() });
+                true
+            },
+        };
+        for entry in self.history.iter_mut().filter(|e| e.same_conversion(&entry)) {
+            entry.pinned = pinned;
+        }
+    }
+
+    /// Loads an entry back into the converter as though it had just been typed
+    fn restore(&mut self, id: u64) {
+        let entry = match self.favourites.iter().chain(self.history.iter()).find(|e| e.id == id) {
+            Some(entry) => entry.clone(),
+            None => return,
+        };
+        if entry.category != self.category {
+            self.set_category(entry.category);
+        }
+        self.last_edited = entry.unit;
+        self.fields[entry.unit].text = format_value(entry.value, self.precision as usize, self.locale);
+        self.convert();
+        // the text may be rounded, the conversions are from the value as it was entered
+        if self.has_value() {
+            self.value = entry.value;
+            self.show(Some(entry.value), false);
+        }
+    }
+
+    /// The history as CSV, one row per converted unit.
+    /// Values are written at full precision with a decimal point whatever the display locale.
+    fn history_csv(&self) -> String {
+        let mut csv = String::from("category,value,unit,converted,converted_unit\n");
+        for entry in self.history.iter().rev() {
+            let category = &CATEGORIES[entry.category];
+            let from = &category.units[entry.unit];
+            for to in category.units.iter().filter(|u| u.symbol != from.symbol) {
+                csv.push_str(&format!("{},{},{},{},{}\n",
+                                      category.name, entry.value, from.symbol,
+                                      units::convert(entry.value, from, to), to.symbol));
+            }
+        }
+        csv
+    }
+
+    /// The settings on the first line: category, number format, precision, and the unit and value
+    /// being converted, separated by tabs. The value is left empty when the input can't be read.
+    /// Then the favourites and the history, newest first, one per line: list, category, unit and value.
+    /// Categories are saved by name, units by their place in the category.
+    fn to_tsv(&self) -> String {
+        let value = if self.has_value() { self.value.to_string() } else { String::new() };
+        let mut tsv = format!("{}\t{:?}\t{}\t{}\t{}\n",
+                              CATEGORIES[self.category].name, self.locale, self.precision, self.last_edited, value);
+        for (list, entries) in [("Favourite", &self.favourites), ("History", &self.history)].iter() {
+            for entry in entries.iter() {
+                tsv.push_str(&format!("{}\t{}\t{}\t{}\n",
+                                      list, CATEGORIES[entry.category].name, entry.unit, entry.value));
+            }
+        }
+        tsv
+    }
+
+    /// Reads the format written by to_tsv, None if the settings can't be read.
+    /// Entry lines that can't be read are skipped.
+    fn from_tsv(text: &str) -> Option<AppData> {
+        let category_index = |name: &str| CATEGORIES.iter().position(|c| c.name == name);
+        let mut lines = text.lines();
+        let settings: Vec<&str> = lines.next()?.split('\t').collect();
+        let (category, locale, precision, unit, value) = match settings.as_slice() {
+            [category, locale, precision, unit, value] => (*category, *locale, *precision, *unit, *value),
+            _ => return None,
+        };
+        let locale = match locale {
+            "Point" => Locale::Point,
+            "Comma" => Locale::Comma,
+            _ => return None,
+        };
+
+        let mut data = AppData::new(category_index(category)?, locale);
+        data.precision = precision.parse::<f64>().ok()?.clamp(0., MAX_PRECISION).round();
+        let unit: usize = unit.parse().ok()?;
+        if unit >= data.fields.len() {
+            return None;
+        }
+        if let Some(value) = parse_value(value, Locale::Point) {
+            data.last_edited = unit;
+            data.fields[unit].text = format_value(value, data.precision as usize, locale);
+            data.convert();
+        }
+
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (list, category, unit, value) = match fields.as_slice() {
+                [list, category, unit, value] => (*list, *category, *unit, *value),
+                _ => continue,
+            };
+            let (category, unit, value) = match (category_index(category), unit.parse::<usize>(), parse_value(value, Locale::Point)) {
+                (Some(category), Ok(unit), Some(value)) if unit < CATEGORIES[category].units.len() => (category, unit, value),
+                _ => continue,
+            };
+            let mut entry = HistoryEntry { id: data.next_id, category, unit, value, summary: String::new(), pinned: false };
+            match list {
+                "Favourite" => {
+                    entry.pinned = true;
+                    data.favourites.push_back(entry);
+                },
+                // a history entry that is also a favourite shares its id, so unpinning finds both
+                "History" if data.history.len() < MAX_HISTORY => {
+                    let favourite = data.favourites.iter()
+                        .find(|f| f.same_conversion(&entry));
+                    if let Some(favourite) = favourite {
+                        entry.id = favourite.id;
+                        entry.pinned = true;
+                    }
+                    data.history.push_back(entry);
+                },
+                _ => continue,
+            }
+            data.next_id += 1;
+        }
+        data.refresh_summaries();
+        Some(data)
+    }
 }
 
-struct FController;
+/// The format has been the same since the converter was first saved
+impl Persist for AppData {
+    const FILE: &'static str = "temperature.tsv";
+    const VERSION: u32 = 1;
+
+    fn to_save(&self) -> String {
+        self.to_tsv()
+    }
 
-impl <W: Widget<AppData>> Controller<AppData, W> for FController {
+    fn from_save(text: &str, _version: u32) -> Option<Self> {
+        AppData::from_tsv(text)
+    }
+}
+
+/// ## Conversion Controller
+/// Conversion is driven by changes to the data rather than by key presses, so pasted text and
+/// commands convert too while arrow keys and other non-editing events do not.
+/// The text is compared before and after the child handles each event, whichever field changed
+/// becomes the source. Writing the converted values into the other fields happens here, after the
+/// comparison, so it is never mistaken for an edit and cannot loop back.
+struct ConvertController;
+
+impl <W: Widget<AppData>> Controller<AppData, W> for ConvertController {
     fn event(
         &mut self,
         child: &mut W,
@@ -72,57 +613,246 @@ impl <W: Widget<AppData>> Controller<AppData, W> for FController {
         data: &mut AppData,
         env: &Env,
     ) {
-        // pass everything to the child widget
-        // needs to occur before other items are updated beware this method in threaded apps
+        let old_category = data.category;
+        let old_format = (data.locale, data.precision);
+        let old_texts: Vec<String> = data.fields.iter().map(|f| f.text.clone()).collect();
+
+        // pass everything to the child widget first so the text boxes can edit the data
         child.event(ctx, event, data, env);
 
-        // intercept the event
-        if let Event::KeyDown(_) = event {
-            f_to_c(data);
+        if data.category != old_category {
+            data.set_category(data.category);
+            return;
         }
 
-        // Note: For multiple events use:
+        if (data.locale, data.precision) != old_format {
+            data.reformat();
+            return;
+        }
 
-        // match event {
-        //     Event::KeyDown(_) => {
-        //         f_to_c(data);
-        //     },
-        //     _ => () // do nothing for other events
-        // }
+        let changed: Vec<usize> = data.fields.iter()
+            .zip(old_texts.iter())
+            .enumerate()
+            .filter(|(_, (field, old))| field.text != **old)
+            .map(|(i, _)| i)
+            .collect();
+
+        match changed.as_slice() {
+            [] => {
+                // enter keeps the current conversion
+                if let Event::KeyDown(e) = event {
+                    if e.key_code == KeyCode::Return {
+                        data.record();
+                    }
+                }
+                return;
+            },
+            [i] => data.last_edited = *i,
+            // several changed at once, keep converting from the field last edited
+            _ => (),
+        }
+
+        data.convert();
     }
 }
 
-struct CController;
 
-impl <W: Widget<AppData>> Controller<AppData, W> for CController {
-    fn event(
+/// New starts over in the same category, Save exports the history, categories and formats are in View
+impl TaskMenu for AppData {
+    fn menu_state(&self) -> MenuState {
+        let categories = CATEGORIES.iter().enumerate().map(|(i, c)| {
+            ViewItem::new(c.name, SET_CATEGORY.with(i)).checked(self.category == i)
+        });
+        let locales = [("1,234.5", Locale::Point), ("1.234,5", Locale::Comma)].iter().map(|&(name, locale)| {
+            let item = ViewItem::new(name, SET_LOCALE.with(locale)).checked(self.locale == locale);
+            if locale == Locale::Point { item.separated() } else { item }
+        });
+        MenuState {
+            new: true,
+            save: !self.history.is_empty(),
+            file: Some(CSV_FILE),
+            cut: true,
+            copy: true,
+            paste: true,
+            view: categories.chain(locales).collect(),
+            ..MenuState::default()
+        }
+    }
+}
+
+/// ## Commands from the history rows
+const TOGGLE_PIN: Selector<u64> = Selector::new("temperature-toggle-pin");
+const RESTORE_ENTRY: Selector<u64> = Selector::new("temperature-restore-entry");
+
+/// ## Commands from the View menu
+const SET_CATEGORY: Selector<usize> = Selector::new("temperature-set-category");
+const SET_LOCALE: Selector<Locale> = Selector::new("temperature-set-locale");
+
+struct Delegate;
+
+impl AppDelegate<AppData> for Delegate {
+    fn command(
         &mut self,
-        child: &mut W,
-        ctx: &mut EventCtx,
-        event: &Event,
+        _ctx: &mut DelegateCtx,
+        _target: Target,
+        cmd: &Command,
         data: &mut AppData,
-        env: &Env,
-    ) {
-        child.event(ctx, event, data, env);
+        _env: &Env
+    ) -> bool {
+        if let Some(id) = cmd.get(TOGGLE_PIN) {
+            data.toggle_pin(*id);
+            false
+        } else if let Some(id) = cmd.get(RESTORE_ENTRY) {
+            data.restore(*id);
+            false
+        } else if let Some(category) = cmd.get(SET_CATEGORY) {
+            data.set_category(*category);
+            false
+        } else if let Some(locale) = cmd.get(SET_LOCALE) {
+            data.locale = *locale;
+            data.reformat();
+            false
+        } else if cmd.is(druid::commands::NEW_FILE) {
+            *data = AppData::new(data.category, data.locale);
+            false
+        } else if let Some(Some(file)) = cmd.get(druid::commands::SAVE_FILE) {
+            if let Err(error) = std::fs::write(file.path(), data.history_csv()) {
+                println!("Error: {}", error);
+            }
+            false
+        } else {
+            true
+        }
+    }
 
-        if let Event::KeyDown(_) = event {
-            c_to_f(data);
+    /// The converter, its settings and the history are saved when the window closes
+    fn window_removed(
+        &mut self,
+        _id: WindowId,
+        data: &mut AppData,
+        _env: &Env,
+        _ctx: &mut DelegateCtx,
+    ) {
+        if let Err(error) = persist::store(data) {
+            println!("Error: {}", error);
         }
     }
 }
 
 
 //LOGIC
-fn f_to_c(data: &mut AppData) {
-    if let Ok(v) = data.tbox_f.parse::<f64>() {
-        let c: f64 = (v - 32.) * (5. / 9.);
-        data.tbox_c = format!("{:.1}", c)
+/// Reads a number written in the given locale. Grouping separators, spaces and apostrophes are
+/// allowed between groups of three digits before the decimal separator, anywhere else the text
+/// is not a number. Exponents (1,5e3) are accepted, infinities and NaN are not.
+fn parse_value(text: &str, locale: Locale) -> Option<f64> {
+    let text = text.trim();
+    let is_group = |c: char| c == locale.group() || c == '\'' || c.is_whitespace();
+
+    // the whole part runs up to the decimal separator or the exponent
+    let whole_end = text.find(|c: char| c == locale.decimal() || c == 'e' || c == 'E').unwrap_or(text.len());
+    let (whole, rest) = text.split_at(whole_end);
+    if rest.contains(is_group) {
+        return None;
+    }
+    let groups: Vec<&str> = whole.trim_start_matches(['-', '+']).split(is_group).collect();
+    if let [first, others @ ..] = groups.as_slice() {
+        if !others.is_empty() && (first.is_empty() || first.len() > 3 || others.iter().any(|g| g.len() != 3)) {
+            return None;
+        }
+    }
+
+    let normalised: String = whole.chars()
+        .filter(|c| !is_group(*c))
+        .chain(rest.chars().map(|c| if c == locale.decimal() { '.' } else { c }))
+        .collect();
+
+    match normalised.parse::<f64>() {
+        Ok(v) if v.is_finite() => Some(v),
+        _ => None,
     }
 }
 
-fn c_to_f(data: &mut AppData) {
-    if let Ok(v) = data.tbox_c.parse::<f64>() {
-        let f: f64 = v * ( 9. /  5.) + 32.;
-        data.tbox_f = format!("{:.1}", f)
+/// Writes a number with a fixed number of decimals in the given locale.
+/// Values too large to read comfortably, or too small to show at this precision,
+/// switch to scientific notation.
+fn format_value(v: f64, precision: usize, locale: Locale) -> String {
+    let abs = v.abs();
+    let smallest = 0.5 * 10f64.powi(-(precision as i32));
+    let text = if v != 0. && (abs >= 1e9 || abs < smallest) {
+        format!("{:.*e}", precision, v)
+    } else {
+        format!("{:.*}", precision, v)
+    };
+    text.replace('.', &locale.decimal().to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grouping_is_only_read_between_thousands() {
+        assert_eq!(parse_value("1.234,5", Locale::Comma), Some(1234.5));
+        assert_eq!(parse_value("-1 234 567", Locale::Point), Some(-1234567.));
+        assert_eq!(parse_value("1,5e3", Locale::Comma), Some(1500.));
+        // the other locale's decimal separator is not a grouping separator
+        assert_eq!(parse_value("21.5", Locale::Comma), None);
+        assert_eq!(parse_value("21,5", Locale::Point), None);
+        assert_eq!(parse_value("1234,567", Locale::Point), None);
+        assert_eq!(parse_value("1.234,5", Locale::Point), None);
+    }
+
+    #[test]
+    fn a_conversion_recorded_again_unpins_its_favourite() {
+        let mut data = AppData::new(units::TEMPERATURE, Locale::Point);
+        data.record();
+        let first = data.history[0].id;
+        data.toggle_pin(first);
+        data.fields[0].text = "1".into();
+        data.convert();
+        data.record();
+        data.fields[0].text = "0".into();
+        data.convert();
+        data.record();
+        assert!(data.history[0].pinned && data.history[0].id != first);
+
+        data.toggle_pin(data.history[0].id);
+        assert!(data.favourites.is_empty());
+        assert!(data.history.iter().all(|e| !e.pinned));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn values_shown_the_same_are_kept_apart() {
+        let mut data = AppData::new(units::TEMPERATURE, Locale::Point);
+        for text in ["21.01", "21.02"] {
+            data.fields[0].text = text.into();
+            data.convert();
+            data.record();
+        }
+        assert_eq!(data.history.len(), 2);
+        assert_eq!(data.history[0].summary, data.history[1].summary);
+
+        // pinning one leaves the other alone
+        data.toggle_pin(data.history[1].id);
+        assert_eq!(data.favourites.len(), 1);
+        assert_eq!(data.favourites[0].value, 21.01);
+        assert!(!data.history[0].pinned && data.history[1].pinned);
+    }
+
+    #[test]
+    fn fewer_decimals_dont_lose_the_value_entered() {
+        let mut data = AppData::new(units::TEMPERATURE, Locale::Point);
+        data.fields[0].text = "21.04".into();
+        data.convert();
+        data.precision = 0.;
+        data.reformat();
+        assert_eq!((data.fields[0].text.as_str(), data.value), ("21", 21.04));
+
+        data.precision = 2.;
+        data.locale = Locale::Comma;
+        data.reformat();
+        assert_eq!(data.fields[0].text, "21,04");
+        assert_eq!(data.fields[1].text, "69,87");
+    }
+}
@@ -1,102 +1,698 @@
 //! # A Timer application to demonstrate concurrent inputs
 //! it's important to note that std::time should be used for tracking time like any other app
 //! and not the timer provided by druid.
+//! Besides the 7GUIs timer there is a countdown, which raises a notification when it runs out,
+//! and a stopwatch that records laps. All three can be paused and resumed.
+//! Any number of named timers can run side by side, they share a single tick source and the
+//! list is saved when the window closes.
+//! Durations can be typed as `1h 30m 15s` or picked on the slider, whose range goes up to
+//! 12 hours.
 
 use druid::{
     AppLauncher, WindowDesc, Widget, PlatformError, Data, Lens, Size, WidgetExt, TimerToken,
-    Event, EventCtx, Env,
+    Event, EventCtx, Env, Target, UpdateCtx, Color, Selector, AppDelegate, DelegateCtx, WindowId,
+    LifeCycle, LifeCycleCtx, Key, Command, FileSpec,
 };
-use druid::widget::{Button, Label, Flex, Align, ProgressBar, Slider, Controller};
+use druid::widget::{Button, Label, Flex, Align, ProgressBar, Slider, Controller, List, Scroll,
+                    Either, TextBox};
+use druid::im::Vector;
+use std::rc::Rc;
 use std::time::{Duration, Instant};
 
+use crate::persist::{self, Persist};
+use crate::menus::{self, MenuState, TaskMenu};
+
 
 const WINDOW_TITLE: &str = "Timer";
-const WINDOW_SIZE: Size = Size::new(300., 150.);
-const MAX_TIME_RANGE: f64 = 30.;
+const WINDOW_SIZE: Size = Size::new(480., 500.);
+const WINDOW_SIZE_MIN: Size = Size::new(400., 250.);
+const POPUP_SIZE: Size = Size::new(250., 100.);
+const DURATION_WIDTH: f64 = 110.;
+const PADDING: f64 = 8.;
+const NAME_WIDTH: f64 = 120.;
+/// Timers opened and saved from the File menu, in the format of the save file
+const TIMERS_FILE: FileSpec = FileSpec::new("Timers", &["tsv"]);
+
+//16ms is around 60fps, ticks are never closer together than this
+static MIN_INTERVAL: Duration = Duration::from_millis(16);
+// the time labels show tenths of a second
+const LABEL_STEP: f64 = 0.1;
+// the stopwatch bar sweeps once a minute
+const STOPWATCH_SWEEP: f64 = 60.;
+// the longest duration the slider can reach, in seconds, picked per timer
+const RANGES: [f64; 7] = [30., 60., 5. * 60., 15. * 60., 60. * 60., 3. * 60. * 60., 12. * 60. * 60.];
 
-//16ms is around 60fps
-static TIMER_INTERVAL: Duration = Duration::from_millis(16);
+const TXT_CLR_INVALID: Key<Color> = Key::new("app.txt.clr.invalid");
 
 
 pub fn main() -> Result<(), PlatformError> {
-    let data = AppData::new();
+    let data = persist::restore()
+        .filter(|data: &AppData| !data.timers.is_empty())
+        .unwrap_or_else(|| AppData::new(Rc::new(SystemClock)));
     let window = WindowDesc::new(build_ui)
         .window_size(WINDOW_SIZE)
+        .with_min_size(WINDOW_SIZE_MIN)
         .title(WINDOW_TITLE)
-        .resizable(false);
-    AppLauncher::with_window(window).launch(data)?;
+        .menu(menus::menu_bar(&data.menu_state()));
+    let delegate = Delegate { main: window.id };
+    AppLauncher::with_window(window)
+        .delegate(delegate)
+        .configure_env(|env, _state| {
+            env.set(TXT_CLR_INVALID, Color::rgb(0.85, 0.05, 0.1));
+        })
+        .launch(data)?;
     Ok(())
 }
 
 
-fn get_time(data: &AppData) -> f64 {
-    if data.progress < 1. {
-            let now = Instant::now();
-    let elapsed = now - data.time;
-    elapsed.as_secs_f64()
-    } else {
-        MAX_TIME_RANGE * data.slider_time
+/// Reads a duration such as `1h 30m 15s`, `90s`, `2.5m` or `1:30:15`.
+/// A plain number is taken as seconds.
+fn parse_duration(text: &str) -> Option<f64> {
+    let text = text.trim().to_ascii_lowercase();
+    if text.is_empty() {
+        return None;
+    }
+
+    // hh:mm:ss, mm:ss
+    if text.contains(':') {
+        let parts: Vec<&str> = text.split(':').collect();
+        if parts.len() > 3 {
+            return None;
+        }
+        let mut total = 0.;
+        for part in parts {
+            let value = part.trim().parse::<f64>().ok().filter(|v| *v >= 0.)?;
+            total = total * 60. + value;
+        }
+        return Some(total);
+    }
+
+    let mut total = 0.;
+    let mut rest = text.as_str();
+    let mut has_unit = false;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Some(total);
+        }
+        let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+        let value = rest[..end].parse::<f64>().ok()?;
+        rest = rest[end..].trim_start();
+        let scale = match rest.chars().next() {
+            Some('h') => 3600.,
+            Some('m') => 60.,
+            Some('s') => 1.,
+            None if !has_unit => return Some(value),
+            _ => return None,
+        };
+        total += value * scale;
+        rest = &rest[1..];
+        has_unit = true;
+    }
+}
+
+/// Writes a duration as `1h 30m 15s`, leaving out parts that are zero, to a tenth of a second
+fn format_duration(seconds: f64) -> String {
+    let tenths = (seconds * 10.).round() as u64;
+    let (hours, minutes, tenths) = (tenths / 36_000, tenths / 600 % 60, tenths % 600);
+
+    let mut parts = Vec::new();
+    if hours > 0 {
+        parts.push(format!("{}h", hours));
+    }
+    if minutes > 0 {
+        parts.push(format!("{}m", minutes));
+    }
+    if tenths > 0 || parts.is_empty() {
+        match tenths % 10 {
+            0 => parts.push(format!("{}s", tenths / 10)),
+            t => parts.push(format!("{}.{}s", tenths / 10, t)),
+        }
+    }
+    parts.join(" ")
+}
+
+/// Writes a time as `hh:mm:ss.t`
+fn format_clock(seconds: f64) -> String {
+    let tenths = (seconds.max(0.) * 10.).round() as u64;
+    format!("{:02}:{:02}:{:02}.{}",
+            tenths / 36_000, tenths / 600 % 60, tenths / 10 % 60, tenths % 10)
+}
+
+/// The time shown under the progress bar, depending on the mode
+fn get_time(data: &TimerData) -> f64 {
+    let elapsed = data.elapsed();
+    match data.mode {
+        Mode::Countdown => (data.duration() - elapsed).max(0.),
+        Mode::Timer | Mode::Stopwatch => elapsed,
     }
 }
 
 
 fn build_ui() -> impl Widget<AppData> {
+    let header = Flex::row()
+        .with_child(TextBox::new().fix_width(NAME_WIDTH).lens(AppData::new_name))
+        .with_spacer(PADDING)
+        .with_child(Button::new("Add Timer")
+            .on_click(|_, data: &mut AppData, _: &_| data.add_timer()));
 
-    let layout = Flex::column()
-        .with_flex_spacer(0.05)
+    let timers = Scroll::new(List::new(build_timer))
+        .vertical()
+        .lens(AppData::timers);
+
+    Flex::column()
+        .with_child(Align::left(header))
+        .with_spacer(PADDING)
+        .with_flex_child(timers, 1.)
+        .padding(PADDING * 2.)
+        .controller(TimeControl::new())
+        .controller(menus::MenuBar)
+}
+
+/// The controls for a single timer
+fn build_timer() -> impl Widget<TimerData> {
+
+    // the stopwatch has no duration, so the slider is swapped for the lap list
+    let duration = Flex::row()
+        .with_child(Label::new("Duration: "))
+        .with_flex_child(Slider::new().lens(TimerData::slider_time), 1.)
+        .with_spacer(PADDING)
+        .with_child(TextBox::new()
+            .fix_width(DURATION_WIDTH)
+            .lens(TimerData::duration_text)
+            .env_scope(|env, data: &TimerData| {
+                if data.duration_invalid {
+                    env.set(druid::theme::LABEL_COLOR, env.get(TXT_CLR_INVALID));
+                }
+            }))
+        .with_spacer(PADDING)
+        // clicking the range moves on to the next one
+        .with_child(Button::new(|data: &TimerData, _: &_| {
+            format!("Max {}", format_duration(data.range))
+        })
+            .on_click(|_, data: &mut TimerData, _: &_| data.next_range()))
+        .controller(DurationControl);
+
+    let laps = List::new(|| {
+        Label::new(|lap: &Lap, _: &_| {
+            format!("Lap {}: {:.2}s (total {:.2}s)", lap.number, lap.split, lap.total)
+        })
+    })
+        .lens(TimerData::laps);
+
+    let mode_controls = Either::new(
+        |data: &TimerData, _: &_| data.mode == Mode::Stopwatch,
+        Align::left(laps),
+        duration,
+    );
+
+    let buttons = Flex::row()
+        .with_child(TextBox::new().fix_width(NAME_WIDTH).lens(TimerData::name))
+        .with_spacer(PADDING)
+        // clicking the mode moves on to the next one
+        .with_child(Button::new(|data: &TimerData, _: &_| format!("{:?}", data.mode))
+            .on_click(|_, data: &mut TimerData, _: &_| data.next_mode()))
+        .with_spacer(PADDING)
+        .with_child(Button::new(|data: &TimerData, _: &_| {
+            if data.running { "Pause".to_string() } else { "Resume".to_string() }
+        })
+            .on_click(|_, data: &mut TimerData, _: &_| data.toggle_pause()))
+        .with_spacer(PADDING)
+        .with_child(Button::new("Lap")
+            .on_click(|_, data: &mut TimerData, _: &_| {
+                if data.mode == Mode::Stopwatch {
+                    data.lap();
+                }
+            }))
+        .with_spacer(PADDING)
+        .with_child(Button::new("Reset")
+            .on_click(|_, data: &mut TimerData, _: &_| data.reset()))
+        .with_flex_spacer(1.)
+        .with_child(Button::new("✕")
+            .on_click(|ctx, data: &mut TimerData, _: &_| {
+                ctx.submit_command(REMOVE_TIMER.with(data.id), None);
+            }));
+
+    Flex::column()
+        .with_child(buttons)
+        .with_spacer(PADDING)
         .with_child(Flex::row()
-            .with_child(Label::new("Elapsed Time: "))
             .with_flex_child(ProgressBar::new()
-                                 .lens(AppData::progress)
-                                 .controller(TimeControl::new())
+                                 .lens(TimerData::progress)
+                                 .controller(BarWidth)
                              , 1.)
+            .with_spacer(PADDING)
+            .with_child(Label::new(|data: &TimerData, _: &_| format_clock(get_time(data))))
         )
-        .with_child(Align::left(Label::new(|data: &AppData, _: &_| {
-            format!("{:.1}s", get_time(data))
-        })))
-        .with_child(Flex::row()
-            .with_child(Label::new("Duration: "))
-            .with_flex_child(Slider::new().lens(AppData::slider_time), 1.)
-        )
-        .with_flex_spacer(0.05)
-        .with_child(Button::new("Reset").on_click(|_, data: &mut AppData, _: &_| {
-            data.progress = 0.;
-            data.time = Instant::now();
-        }))
-        .with_flex_spacer(0.05);
+        .with_spacer(PADDING)
+        .with_child(mode_controls)
+        .padding(PADDING)
+        .border(Color::grey(0.6), 1.)
+        .padding((0., PADDING / 2.))
+}
 
-    Align::centered(layout)
+/// Shown once when a countdown runs out
+fn build_popup(name: String) -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(Label::new(format!("Time's up: {}", name)))
+        .with_spacer(PADDING)
+        .with_child(Button::new("OK")
+            .on_click(|ctx, _data: &mut AppData, _: &_| {
+                ctx.submit_command(druid::commands::CLOSE_WINDOW, Target::Window(ctx.window_id()));
+            }))
+        .padding(PADDING * 2.)
 }
 
+/// ## Clock
+/// All time is read through a clock held in the app data rather than from `Instant::now()`,
+/// so tests can swap in a clock they advance by hand.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The clock used when running the app
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// What the progress bar and time label track
+#[derive(Clone, Copy, Data, PartialEq, Debug)]
+enum Mode {
+    /// Fills up over the duration then stops, as in the 7GUIs spec
+    Timer,
+    /// Empties over the duration and notifies when it reaches zero
+    Countdown,
+    /// Runs without end and records laps
+    Stopwatch,
+}
+
+/// A recorded stopwatch lap, times are in seconds
+#[derive(Clone, Data)]
+struct Lap {
+    number: usize,
+    split: f64,
+    total: f64,
+}
+
+/// The state of every timer
 #[derive(Clone, Data, Lens)]
 struct AppData {
+    timers: Vector<TimerData>,
+    // name for the next timer added, left empty they are numbered
+    new_name: String,
+    next_id: u64,
+    clock: Rc<dyn Clock>,
+}
+
+impl AppData {
+    fn new(clock: Rc<dyn Clock>) -> AppData {
+        let mut data = AppData {
+            timers: Vector::new(),
+            new_name: String::new(),
+            next_id: 0,
+            clock,
+        };
+        data.add_timer();
+        data
+    }
+
+    fn add_timer(&mut self) {
+        let name = match self.new_name.trim() {
+            "" => format!("Timer {}", self.next_id + 1),
+            name => name.to_string(),
+        };
+        self.timers.push_back(TimerData::new(self.next_id, name, self.clock.clone()));
+        self.next_id += 1;
+    }
+
+    fn remove_timer(&mut self, id: u64) {
+        self.timers.retain(|t| t.id != id);
+    }
+
+    /// How long until something on screen next changes, None when nothing is moving.
+    /// This is the shortest of the running timers' intervals, see `TimerData::tick_interval`.
+    fn tick_interval(&self, bar_width: f64) -> Option<Duration> {
+        self.timers.iter()
+            .filter(|t| t.is_ticking())
+            .map(|t| t.tick_interval(bar_width))
+            .fold(None, |min: Option<f64>, t| Some(min.map_or(t, |m| m.min(t))))
+            .map(|secs| Duration::from_secs_f64(secs).max(MIN_INTERVAL))
+    }
+
+    /// Updates every timer from the clock, returning the names of countdowns that just finished
+    fn tick(&mut self) -> Vec<String> {
+        let mut finished = Vec::new();
+        for timer in self.timers.iter_mut() {
+            timer.update_progress();
+            if timer.countdown_finished() {
+                finished.push(timer.name.clone());
+            }
+        }
+        finished
+    }
+
+    /// One timer per line: name, mode, slider position, elapsed seconds and slider range,
+    /// separated by tabs.
+    /// Running timers are saved as paused as there is no telling how long until the next run.
+    fn to_tsv(&self) -> String {
+        self.timers.iter()
+            .map(|t| format!("{}\t{:?}\t{}\t{}\t{}\n",
+                             t.name.replace(['\t', '\n'], " "),
+                             t.mode, t.slider_time, t.elapsed(), t.range))
+            .collect()
+    }
+
+    /// Reads the format written by to_tsv, lines that can't be read are skipped.
+    /// Saves from before the range could be picked have no range column and use the first one.
+    fn from_tsv(text: &str, clock: Rc<dyn Clock>) -> AppData {
+        let mut data = AppData {
+            timers: Vector::new(),
+            new_name: String::new(),
+            next_id: 0,
+            clock,
+        };
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (name, mode, slider, elapsed, range) = match fields.as_slice() {
+                [name, mode, slider, elapsed] => (*name, *mode, *slider, *elapsed, None),
+                [name, mode, slider, elapsed, range] => (*name, *mode, *slider, *elapsed, Some(*range)),
+                _ => continue,
+            };
+            let range = match range.map(str::parse::<f64>) {
+                None => RANGES[0],
+                Some(Ok(range)) if range > 0. => range,
+                Some(_) => continue,
+            };
+            let mode = match mode {
+                "Timer" => Mode::Timer,
+                "Countdown" => Mode::Countdown,
+                "Stopwatch" => Mode::Stopwatch,
+                _ => continue,
+            };
+            if let (Ok(slider), Ok(elapsed)) = (slider.parse::<f64>(), elapsed.parse::<f64>()) {
+                let mut timer = TimerData::new(data.next_id, name.to_string(), data.clock.clone());
+                timer.mode = mode;
+                timer.slider_time = slider.clamp(0., 1.);
+                timer.range = range;
+                timer.sync_text();
+                timer.accumulated = elapsed.max(0.);
+                timer.running = false;
+                timer.notified = mode == Mode::Countdown && timer.accumulated >= timer.duration();
+                timer.update_progress();
+                data.timers.push_back(timer);
+                data.next_id += 1;
+            }
+        }
+        data
+    }
+}
+
+/// A single named timer
+#[derive(Clone, Data, Lens)]
+struct TimerData {
+    id: u64,
+    name: String,
     // because data is not available for time
+    // the start of the current running stretch, time before it is held in `accumulated`
     #[data(same_fn = "PartialEq::eq")]
     time: Instant,
+    accumulated: f64,
+    running: bool,
+    mode: Mode,
     progress: f64,
     slider_time: f64,
+    // the duration at the right end of the slider, in seconds
+    range: f64,
+    // the duration as typed, kept in step with the slider
+    duration_text: String,
+    duration_invalid: bool,
+    laps: Vector<Lap>,
+    // set once the countdown has raised its notification
+    notified: bool,
+    // compared by pointer, the clock never changes once the app is running
+    clock: Rc<dyn Clock>,
 }
 
-impl AppData {
-    fn new() -> AppData {
-        AppData {
-            time: Instant::now(),
+impl TimerData {
+    fn new(id: u64, name: String, clock: Rc<dyn Clock>) -> TimerData {
+        let mut timer = TimerData {
+            id,
+            name,
+            time: clock.now(),
+            accumulated: 0.,
+            running: true,
+            mode: Mode::Timer,
             progress: 0.,
             slider_time: 0.5,
+            range: RANGES[0],
+            duration_text: String::new(),
+            duration_invalid: false,
+            laps: Vector::new(),
+            notified: false,
+            clock,
+        };
+        timer.sync_text();
+        timer
+    }
+
+    /// Seconds until this timer visibly changes: the next tenth on the label or the next
+    /// pixel of the progress bar, whichever comes first.
+    /// A timer that is about to fill is woken exactly when it does, so it stops on time.
+    fn tick_interval(&self, bar_width: f64) -> f64 {
+        let span = match self.mode {
+            Mode::Timer | Mode::Countdown => self.duration(),
+            Mode::Stopwatch => STOPWATCH_SWEEP,
+        };
+        let pixel = if bar_width > 0. { span / bar_width } else { LABEL_STEP };
+        let step = LABEL_STEP.min(pixel);
+
+        match self.mode {
+            Mode::Timer | Mode::Countdown => step.min(self.duration() - self.elapsed()),
+            Mode::Stopwatch => step,
+        }
+    }
+
+    /// Moves on to the next mode, each mode starts from zero
+    fn next_mode(&mut self) {
+        self.mode = match self.mode {
+            Mode::Timer => Mode::Countdown,
+            Mode::Countdown => Mode::Stopwatch,
+            Mode::Stopwatch => Mode::Timer,
+        };
+        self.reset();
+    }
+
+    fn duration(&self) -> f64 {
+        self.slider_time * self.range
+    }
+
+    /// Sets the duration in seconds, widening the slider range if it does not reach that far.
+    /// Like moving the slider, `rebase` must be called first.
+    fn set_duration(&mut self, seconds: f64) {
+        if seconds > self.range {
+            self.range = RANGES.iter().copied().find(|&range| range >= seconds).unwrap_or(seconds);
+        }
+        self.slider_time = seconds / self.range;
+    }
+
+    /// Moves on to the next slider range, wrapping around to the shortest.
+    /// The duration is kept unless it no longer fits, then the slider ends up at the far right.
+    fn next_range(&mut self) {
+        let duration = self.duration();
+        self.range = RANGES.iter().copied().find(|&range| range > self.range).unwrap_or(RANGES[0]);
+        self.slider_time = (duration / self.range).min(1.);
+    }
+
+    /// Writes the current duration into the text field
+    fn sync_text(&mut self) {
+        self.duration_text = format_duration(self.duration());
+        self.duration_invalid = false;
+    }
+
+    /// Seconds spent running, pauses excluded.
+    /// The timer and countdown stop counting once they reach the duration, this is worked out
+    /// from the clock rather than from the progress bar so it does not depend on how often it ticks.
+    /// If the duration has since been moved below the elapsed time, time stays where it was.
+    fn elapsed(&self) -> f64 {
+        if !self.running {
+            return self.accumulated;
+        }
+        let elapsed = self.accumulated + (self.clock.now() - self.time).as_secs_f64();
+        match self.mode {
+            Mode::Timer | Mode::Countdown => elapsed.min(self.duration().max(self.accumulated)),
+            Mode::Stopwatch => elapsed,
+        }
+    }
+
+    /// Folds the elapsed time into `accumulated` and starts a new running stretch from now.
+    /// Must be called with the old duration still in place before the duration changes,
+    /// so that time already counted is kept and a full timer can carry on if the duration grows.
+    fn rebase(&mut self) {
+        self.accumulated = self.elapsed();
+        self.time = self.clock.now();
+    }
+
+    /// Whether time is still moving, the tick loop only runs while this is true
+    fn is_ticking(&self) -> bool {
+        self.running && match self.mode {
+            Mode::Timer | Mode::Countdown => self.elapsed() < self.duration(),
+            Mode::Stopwatch => true,
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        if self.running {
+            self.accumulated = self.elapsed();
+            self.running = false;
+        } else {
+            self.time = self.clock.now();
+            self.running = true;
+        }
+    }
+
+    /// Starts again from zero, a paused timer stays paused
+    fn reset(&mut self) {
+        self.time = self.clock.now();
+        self.accumulated = 0.;
+        self.laps.clear();
+        self.notified = false;
+        self.update_progress();
+    }
+
+    fn lap(&mut self) {
+        let total = self.elapsed();
+        let previous = self.laps.back().map_or(0., |lap| lap.total);
+        self.laps.push_back(Lap {
+            number: self.laps.len() + 1,
+            split: total - previous,
+            total,
+        });
+    }
+
+    /// Updates the progress bar for the current mode
+    fn update_progress(&mut self) {
+        let elapsed = self.elapsed();
+        let duration = self.duration();
+        self.progress = match self.mode {
+            // a zero duration is always full or empty
+            Mode::Timer if duration <= 0. => 1.,
+            Mode::Countdown if duration <= 0. => 0.,
+            Mode::Timer => (elapsed / duration).min(1.),
+            Mode::Countdown => (1. - elapsed / duration).max(0.),
+            // sweeps once a minute
+            Mode::Stopwatch => (elapsed % STOPWATCH_SWEEP) / STOPWATCH_SWEEP,
+        };
+    }
+
+    /// True the first time a countdown is seen to have run out
+    fn countdown_finished(&mut self) -> bool {
+        if self.mode == Mode::Countdown && !self.notified && self.elapsed() >= self.duration() {
+            self.notified = true;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Keeps the slider and the duration text in step.
+/// Either way the time already counted is kept when the duration changes, see `TimerData::rebase`
+struct DurationControl;
+
+impl <W: Widget<TimerData>> Controller<TimerData, W> for DurationControl {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut TimerData,
+        env: &Env,
+    ) {
+        let (old_slider, old_range) = (data.slider_time, data.range);
+        let old_text = data.duration_text.clone();
+
+        child.event(ctx, event, data, env);
+
+        // the time counted so far is worked out with the duration as it was before the change
+        let rebase = |data: &mut TimerData| {
+            let (slider, range) = (data.slider_time, data.range);
+            data.slider_time = old_slider;
+            data.range = old_range;
+            data.rebase();
+            data.slider_time = slider;
+            data.range = range;
+        };
+
+        if data.slider_time != old_slider || data.range != old_range {
+            rebase(data);
+            data.sync_text();
+            data.update_progress();
+        } else if data.duration_text != old_text {
+            match parse_duration(&data.duration_text) {
+                Some(seconds) => {
+                    rebase(data);
+                    data.set_duration(seconds);
+                    data.duration_invalid = false;
+                    data.update_progress();
+                }
+                None => data.duration_invalid = true,
+            }
         }
     }
 }
 
+/// Reports the width of a progress bar to the TimeControl whenever it is laid out
+struct BarWidth;
+
+impl <W: Widget<TimerData>> Controller<TimerData, W> for BarWidth {
+    fn lifecycle(
+        &mut self,
+        child: &mut W,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &TimerData,
+        env: &Env,
+    ) {
+        if let LifeCycle::Size(size) = event {
+            ctx.submit_command(BAR_WIDTH.with(size.width), None);
+        }
+        child.lifecycle(ctx, event, data, env);
+    }
+}
+
+/// Drives the progress bars while time is moving.
+/// There is one of these for the whole window rather than one per timer, every tick updates
+/// all of the timers together.
+/// Ticks are only as frequent as the display needs, one per tenth of a second on the labels or
+/// one per pixel on the bars, rather than a fixed 60 per second. For a 30s timer on a 300px bar
+/// this is one wakeup every 100ms instead of every 16ms.
+/// The tick loop stops once nothing changes (paused or full) and is restarted from
+/// `update` when the data changes in a way that starts time again, such as a longer duration.
+/// Druid 0.6 doesn't tell a window it has been minimised, so a hidden window keeps ticking.
 struct TimeControl {
     timer_id: TimerToken,
+    bar_width: f64,
 }
 
 impl TimeControl {
     fn new() -> Self {
         TimeControl {
             timer_id: TimerToken::INVALID,
+            bar_width: 0.,
         }
     }
+
+    /// Schedules the next tick, or stops the loop if there is nothing to show
+    fn schedule(&mut self, ctx: &mut EventCtx, data: &AppData) {
+        self.timer_id = match data.tick_interval(self.bar_width) {
+            Some(interval) => ctx.request_timer(interval),
+            None => TimerToken::INVALID,
+        };
+    }
 }
 
 impl <W: Widget<AppData>> Controller<AppData, W> for TimeControl {
@@ -113,24 +709,407 @@ impl <W: Widget<AppData>> Controller<AppData, W> for TimeControl {
         match event {
             // on start
             Event::WindowConnected => {
-                self.timer_id = ctx.request_timer(TIMER_INTERVAL)
+                self.schedule(ctx, data)
+            },
+            Event::Command(cmd) if cmd.is(BAR_WIDTH) => {
+                self.bar_width = *cmd.get_unchecked(BAR_WIDTH);
+                return;
             },
             // This is not precise so we must calculate the actual time
-            Event::Timer(id) => (
-                if *id == self.timer_id {
-                        //determine amount of time elapsed
-                        let current_time = Instant::now();
-                        let elapsed_time = current_time - data.time;
-                        // update based on time elapsed since timer was started
-                        data.progress = elapsed_time.as_secs_f64() / (data.slider_time * MAX_TIME_RANGE);
-                //request an update
-                self.timer_id = ctx.request_timer(TIMER_INTERVAL)
+            Event::Timer(id) if *id == self.timer_id => {
+                // update based on time elapsed since each timer was started
+                for name in data.tick() {
+                    let popup = WindowDesc::new(move || build_popup(name))
+                        .window_size(POPUP_SIZE)
+                        .resizable(false)
+                        .title("Time's up");
+                    ctx.new_window(popup);
                 }
-            ),
+
+                //request an update, unless time has stopped
+                self.schedule(ctx, data);
+            },
+            // the remove buttons live inside the list, so removal is handled up here
+            Event::Command(cmd) if cmd.is(REMOVE_TIMER) => {
+                data.remove_timer(*cmd.get_unchecked(REMOVE_TIMER));
+                return;
+            },
             _ => () // do nothing for other events
         }
 
         // pass everything else to the child widget
         child.event(ctx, event, data, env);
     }
-}
\ No newline at end of file
+
+    fn update(
+        &mut self,
+        child: &mut W,
+        ctx: &mut UpdateCtx,
+        old_data: &AppData,
+        data: &AppData,
+        env: &Env,
+    ) {
+        // restart the loop if time has started moving again
+        if self.timer_id == TimerToken::INVALID {
+            if let Some(interval) = data.tick_interval(self.bar_width) {
+                self.timer_id = ctx.request_timer(interval);
+            }
+        }
+        child.update(ctx, old_data, data, env);
+    }
+}
+
+const REMOVE_TIMER: Selector<u64> = Selector::new("timer-remove");
+const BAR_WIDTH: Selector<f64> = Selector::new("timer-bar-width");
+
+/// Restored timers run on the system clock.
+/// The format hasn't changed since before there were versions
+impl Persist for AppData {
+    const FILE: &'static str = "timers.tsv";
+    const VERSION: u32 = 1;
+
+    fn to_save(&self) -> String {
+        self.to_tsv()
+    }
+
+    fn from_save(text: &str, _version: u32) -> Option<Self> {
+        let data = AppData::from_tsv(text, Rc::new(SystemClock));
+        // unreadable lines are skipped, a file of nothing but those isn't a timers file
+        if data.timers.is_empty() && !text.trim().is_empty() {
+            return None;
+        }
+        Some(data)
+    }
+}
+
+/// New starts again with a single timer, Open and Save work on files like the save file
+impl TaskMenu for AppData {
+    fn menu_state(&self) -> MenuState {
+        MenuState {
+            new: true,
+            open: true,
+            save: true,
+            file: Some(TIMERS_FILE),
+            cut: true,
+            copy: true,
+            paste: true,
+            ..MenuState::default()
+        }
+    }
+}
+
+/// Handles the File menu and saves the timers when the main window closes
+struct Delegate {
+    // the "Time's up" popups close without saving
+    main: WindowId,
+}
+
+impl AppDelegate<AppData> for Delegate {
+    fn command(
+        &mut self,
+        _ctx: &mut DelegateCtx,
+        _target: Target,
+        cmd: &Command,
+        data: &mut AppData,
+        _env: &Env,
+    ) -> bool {
+        if cmd.is(druid::commands::NEW_FILE) {
+            *data = AppData::new(data.clock.clone());
+            false
+        } else if let Some(file) = cmd.get(druid::commands::OPEN_FILE) {
+            match std::fs::read_to_string(file.path()) {
+                Ok(text) => {
+                    let opened = AppData::from_tsv(&text, data.clock.clone());
+                    if opened.timers.is_empty() {
+                        println!("Error: no timers in {}", file.path().display());
+                    } else {
+                        *data = opened;
+                    }
+                },
+                Err(error) => println!("Error: {}", error),
+            }
+            false
+        } else if let Some(Some(file)) = cmd.get(druid::commands::SAVE_FILE) {
+            if let Err(error) = std::fs::write(file.path(), data.to_tsv()) {
+                println!("Error: {}", error);
+            }
+            false
+        } else {
+            true
+        }
+    }
+
+    fn window_removed(
+        &mut self,
+        id: WindowId,
+        data: &mut AppData,
+        _env: &Env,
+        _ctx: &mut DelegateCtx,
+    ) {
+        if id != self.main {
+            return;
+        }
+        if let Err(error) = persist::store(data) {
+            println!("Error: {}", error);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// A clock that only moves when told to
+    struct ManualClock {
+        start: Instant,
+        offset: Cell<Duration>,
+    }
+
+    impl ManualClock {
+        fn new() -> Rc<Self> {
+            Rc::new(ManualClock {
+                start: Instant::now(),
+                offset: Cell::new(Duration::from_secs(0)),
+            })
+        }
+
+        fn advance(&self, secs: f64) {
+            self.offset.set(self.offset.get() + Duration::from_secs_f64(secs));
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.start + self.offset.get()
+        }
+    }
+
+    fn setup() -> (Rc<ManualClock>, TimerData) {
+        let clock = ManualClock::new();
+        let data = TimerData::new(0, "Test".into(), clock.clone());
+        (clock, data)
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn progress_follows_clock() {
+        let (clock, mut data) = setup();
+        data.slider_time = 0.5;
+
+        clock.advance(7.5);
+        data.update_progress();
+        assert_close(data.progress, 0.5);
+        assert_close(get_time(&data), 7.5);
+
+        // the label stops at the duration
+        clock.advance(30.);
+        data.update_progress();
+        assert_close(get_time(&data), 15.);
+    }
+
+    #[test]
+    fn duration_change_mid_run() {
+        let (clock, mut data) = setup();
+        data.slider_time = 0.5;
+
+        clock.advance(10.);
+        data.update_progress();
+        assert_close(data.progress, 10. / 15.);
+
+        data.slider_time = 1.;
+        data.update_progress();
+        assert_close(data.progress, 10. / 30.);
+    }
+
+    #[test]
+    fn full_timer_resumes_when_duration_grows() {
+        let (clock, mut data) = setup();
+        data.slider_time = 0.5;
+
+        clock.advance(20.);
+        data.update_progress();
+        assert!(!data.is_ticking());
+        assert_close(data.elapsed(), 15.);
+
+        // only time after the duration grows is counted, not the time spent full
+        data.rebase();
+        data.slider_time = 1.;
+        assert!(data.is_ticking());
+        clock.advance(5.);
+        assert_close(data.elapsed(), 20.);
+    }
+
+    #[test]
+    fn shrinking_duration_stops_elapsed() {
+        let (clock, mut data) = setup();
+        data.slider_time = 1.;
+
+        clock.advance(10.);
+        data.rebase();
+        data.slider_time = 0.2;
+        clock.advance(5.);
+        data.update_progress();
+        assert!(!data.is_ticking());
+        assert_close(data.elapsed(), 10.);
+        assert_close(data.progress, 1.);
+    }
+
+    #[test]
+    fn pause_excludes_time() {
+        let (clock, mut data) = setup();
+
+        clock.advance(2.);
+        data.toggle_pause();
+        clock.advance(100.);
+        assert_close(data.elapsed(), 2.);
+
+        data.toggle_pause();
+        clock.advance(1.);
+        assert_close(data.elapsed(), 3.);
+    }
+
+    #[test]
+    fn reset_restarts_from_zero() {
+        let (clock, mut data) = setup();
+
+        clock.advance(5.);
+        data.reset();
+        assert_close(data.elapsed(), 0.);
+        assert_close(data.progress, 0.);
+
+        clock.advance(3.);
+        assert_close(data.elapsed(), 3.);
+
+        // a paused timer stays paused through a reset
+        data.toggle_pause();
+        data.reset();
+        clock.advance(3.);
+        assert!(!data.running);
+        assert_close(data.elapsed(), 0.);
+    }
+
+    #[test]
+    fn countdown_notifies_once() {
+        let (clock, mut data) = setup();
+        data.mode = Mode::Countdown;
+        data.slider_time = 0.5;
+
+        clock.advance(14.);
+        assert!(!data.countdown_finished());
+        clock.advance(1.);
+        assert!(data.countdown_finished());
+        assert!(!data.countdown_finished());
+    }
+
+    #[test]
+    fn laps_record_splits() {
+        let (clock, mut data) = setup();
+        data.mode = Mode::Stopwatch;
+
+        clock.advance(1.5);
+        data.lap();
+        clock.advance(2.);
+        data.lap();
+
+        assert_eq!(data.laps.len(), 2);
+        assert_close(data.laps[1].split, 2.);
+        assert_close(data.laps[1].total, 3.5);
+    }
+
+    #[test]
+    fn timers_run_independently() {
+        let clock = ManualClock::new();
+        let mut data = AppData::new(clock.clone());
+        data.add_timer();
+        data.timers[1].mode = Mode::Countdown;
+        data.timers[0].toggle_pause();
+
+        clock.advance(15.);
+        let finished = data.tick();
+        assert_eq!(finished, vec![data.timers[1].name.clone()]);
+        assert_close(data.timers[0].elapsed(), 0.);
+        assert_eq!(data.tick_interval(300.), None);
+
+        data.remove_timer(data.timers[0].id);
+        assert_eq!(data.timers.len(), 1);
+    }
+
+    #[test]
+    fn timers_round_trip_through_tsv() {
+        let clock = ManualClock::new();
+        let mut data = AppData::new(clock.clone());
+        data.add_timer();
+        data.timers[1].name = "Tea\tbreak".into();
+        data.timers[1].mode = Mode::Stopwatch;
+        clock.advance(4.);
+
+        let restored = AppData::from_tsv(&data.to_tsv(), clock.clone());
+        assert_eq!(restored.timers.len(), 2);
+        assert_eq!(restored.timers[1].name, "Tea break");
+        assert_eq!(restored.timers[1].mode, Mode::Stopwatch);
+        assert!(!restored.timers[1].running);
+        assert_close(restored.timers[1].elapsed(), 4.);
+    }
+
+    #[test]
+    fn duration_text_round_trips() {
+        assert_eq!(parse_duration("1h 30m 15s"), Some(5415.));
+        assert_eq!(parse_duration("1H30M"), Some(5400.));
+        assert_eq!(parse_duration("2.5m"), Some(150.));
+        assert_eq!(parse_duration("90"), Some(90.));
+        assert_eq!(parse_duration("1:30:15"), Some(5415.));
+        assert_eq!(parse_duration("1m 30"), None);
+        assert_eq!(parse_duration("-5s"), None);
+        assert_eq!(parse_duration(""), None);
+
+        assert_eq!(format_duration(5415.), "1h 30m 15s");
+        assert_eq!(format_duration(7.46), "7.5s");
+        assert_eq!(format_duration(0.), "0s");
+        assert_eq!(format_clock(5415.25), "01:30:15.3");
+    }
+
+    #[test]
+    fn typed_duration_widens_range() {
+        let (_, mut data) = setup();
+        data.set_duration(5415.);
+        assert_eq!(data.range, 3. * 60. * 60.);
+        assert_close(data.duration(), 5415.);
+
+        // stepping through the ranges keeps the duration until it no longer fits
+        data.next_range();
+        assert_close(data.duration(), 5415.);
+        data.next_range();
+        assert_eq!(data.range, RANGES[0]);
+        assert_close(data.duration(), RANGES[0]);
+    }
+
+    #[test]
+    fn tick_rate_follows_display() {
+        let clock = ManualClock::new();
+        let mut data = AppData::new(clock.clone());
+        data.timers[0].slider_time = 1.;
+
+        // a 30s timer on a 300px bar moves a pixel every 100ms, the same as the label
+        let interval = data.tick_interval(300.).unwrap();
+        assert_eq!(interval, Duration::from_millis(100));
+        // that is about a sixth of the wakeups of a fixed 16ms tick
+        assert!(interval.as_secs_f64() / MIN_INTERVAL.as_secs_f64() > 6.);
+
+        // a wide bar needs more frequent ticks, but never more than 60 a second
+        assert_eq!(data.tick_interval(600.).unwrap(), Duration::from_millis(50));
+        assert_eq!(data.tick_interval(1e6).unwrap(), MIN_INTERVAL);
+
+        // the last tick lands exactly on the end of the timer
+        clock.advance(29.95);
+        assert!((data.tick_interval(300.).unwrap().as_secs_f64() - 0.05).abs() < 1e-6);
+
+        // nothing moving, no ticks
+        data.timers[0].toggle_pause();
+        assert_eq!(data.tick_interval(300.), None);
+    }
+}
@@ -0,0 +1,332 @@
+//! # A unit registry and conversion engine
+//! Every unit is defined against the base unit of its category as `base = value * factor + offset`.
+//! Factors and offsets are stored as exact fractions so that chaining two units together does not
+//! lose precision, the floating point conversion only happens once at the very end.
+
+/// ## Exact fractions
+/// Only the operations needed to combine two units are implemented.
+/// Each operation returns None if the result no longer fits, conversion then falls back to floats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ratio {
+    num: i128,
+    den: i128,
+}
+
+impl Ratio {
+    pub const ZERO: Ratio = Ratio::int(0);
+    pub const ONE: Ratio = Ratio::int(1);
+
+    /// The denominator must not be zero
+    pub const fn new(num: i128, den: i128) -> Self {
+        Ratio { num, den }
+    }
+
+    pub const fn int(num: i128) -> Self {
+        Ratio { num, den: 1 }
+    }
+
+    /// Reduces the fraction and keeps the sign on the numerator
+    fn reduced(self) -> Self {
+        let g = gcd(self.num, self.den).max(1);
+        let sign = if self.den < 0 { -1 } else { 1 };
+        Ratio {
+            num: sign * self.num / g,
+            den: sign * self.den / g,
+        }
+    }
+
+    pub fn mul(self, other: Ratio) -> Option<Ratio> {
+        // cross reduce first to keep the intermediate values small
+        let a = self.reduced();
+        let b = other.reduced();
+        let g1 = gcd(a.num, b.den).max(1);
+        let g2 = gcd(b.num, a.den).max(1);
+        Some(Ratio {
+            num: (a.num / g1).checked_mul(b.num / g2)?,
+            den: (a.den / g2).checked_mul(b.den / g1)?,
+        }.reduced())
+    }
+
+    pub fn div(self, other: Ratio) -> Option<Ratio> {
+        if other.num == 0 {
+            return None;
+        }
+        self.mul(Ratio { num: other.den, den: other.num })
+    }
+
+    pub fn add(self, other: Ratio) -> Option<Ratio> {
+        let a = self.reduced();
+        let b = other.reduced();
+        let g = gcd(a.den, b.den).max(1);
+        let den = (a.den / g).checked_mul(b.den)?;
+        let num = a.num.checked_mul(b.den / g)?.checked_add(b.num.checked_mul(a.den / g)?)?;
+        Some(Ratio { num, den }.reduced())
+    }
+
+    pub fn sub(self, other: Ratio) -> Option<Ratio> {
+        self.add(Ratio { num: -other.num, den: other.den })
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+/// ## Units
+pub struct Unit {
+    pub name: &'static str,
+    pub symbol: &'static str,
+    factor: Ratio,
+    offset: Ratio,
+}
+
+impl Unit {
+    /// A unit that is a plain multiple of the base unit
+    const fn scaled(name: &'static str, symbol: &'static str, factor: Ratio) -> Self {
+        Unit { name, symbol, factor, offset: Ratio::ZERO }
+    }
+
+    /// A unit whose zero point differs from the base unit, such as degrees Celsius
+    const fn affine(name: &'static str, symbol: &'static str, factor: Ratio, offset: Ratio) -> Self {
+        Unit { name, symbol, factor, offset }
+    }
+
+    /// The value of this unit, in base units
+    pub fn to_base(&self, value: f64) -> f64 {
+        value * self.factor.to_f64() + self.offset.to_f64()
+    }
+}
+
+/// A group of units that can be converted between each other
+pub struct Category {
+    pub name: &'static str,
+    pub units: &'static [Unit],
+    pub limit: Option<Limit>,
+}
+
+/// The smallest physically meaningful value of a category, in base units
+pub struct Limit {
+    pub name: &'static str,
+    minimum: Ratio,
+}
+
+impl Limit {
+    /// The limit written in a unit of its category
+    pub fn in_unit(&self, unit: &Unit) -> f64 {
+        match self.minimum.sub(unit.offset).and_then(|v| v.div(unit.factor)) {
+            Some(v) => v.to_f64(),
+            None => (self.minimum.to_f64() - unit.offset.to_f64()) / unit.factor.to_f64(),
+        }
+    }
+
+    /// Whether a value in the given unit lies below the limit
+    pub fn is_below(&self, value: f64, unit: &Unit) -> bool {
+        value < self.in_unit(unit)
+    }
+}
+
+/// Converts a value between two units of the same category.
+/// The factor and offset from one unit to the other are combined exactly where they fit,
+/// so only a single multiplication and addition is done in floating point.
+pub fn convert(value: f64, from: &Unit, to: &Unit) -> f64 {
+    // to = (value * from.factor + from.offset - to.offset) / to.factor
+    let exact = from.factor.div(to.factor).and_then(|k| {
+        let c = from.offset.sub(to.offset)?.div(to.factor)?;
+        Some((k, c))
+    });
+
+    match exact {
+        Some((k, c)) => value * k.to_f64() + c.to_f64(),
+        None => (from.to_base(value) - to.offset.to_f64()) / to.factor.to_f64(),
+    }
+}
+
+/// Index of the temperature category, the preset used by the Temperature Converter task
+pub const TEMPERATURE: usize = 0;
+
+/// ## Registry
+/// The first unit of each category is the one new values are entered in
+pub const CATEGORIES: &[Category] = &[
+    Category {
+        name: "Temperature",
+        limit: Some(Limit { name: "absolute zero", minimum: Ratio::ZERO }),
+        // base unit: kelvin
+        units: &[
+            Unit::affine("Celsius", "℃", Ratio::ONE, Ratio::new(27_315, 100)),
+            Unit::affine("Fahrenheit", "℉", Ratio::new(5, 9), Ratio::new(45_967, 180)),
+            Unit::scaled("Kelvin", "K", Ratio::ONE),
+            Unit::scaled("Rankine", "°R", Ratio::new(5, 9)),
+        ],
+    },
+    Category {
+        name: "Length",
+        limit: None,
+        // base unit: metre
+        units: &[
+            Unit::scaled("Metre", "m", Ratio::ONE),
+            Unit::scaled("Kilometre", "km", Ratio::int(1000)),
+            Unit::scaled("Centimetre", "cm", Ratio::new(1, 100)),
+            Unit::scaled("Millimetre", "mm", Ratio::new(1, 1000)),
+            Unit::scaled("Inch", "in", Ratio::new(254, 10_000)),
+            Unit::scaled("Foot", "ft", Ratio::new(3048, 10_000)),
+            Unit::scaled("Yard", "yd", Ratio::new(9144, 10_000)),
+            Unit::scaled("Mile", "mi", Ratio::new(1_609_344, 1000)),
+            Unit::scaled("Nautical mile", "nmi", Ratio::int(1852)),
+        ],
+    },
+    Category {
+        name: "Mass",
+        limit: Some(Limit { name: "zero mass", minimum: Ratio::ZERO }),
+        // base unit: kilogram
+        units: &[
+            Unit::scaled("Kilogram", "kg", Ratio::ONE),
+            Unit::scaled("Gram", "g", Ratio::new(1, 1000)),
+            Unit::scaled("Tonne", "t", Ratio::int(1000)),
+            Unit::scaled("Pound", "lb", Ratio::new(45_359_237, 100_000_000)),
+            Unit::scaled("Ounce", "oz", Ratio::new(45_359_237, 1_600_000_000)),
+            Unit::scaled("Stone", "st", Ratio::new(635_029_318, 100_000_000)),
+        ],
+    },
+    Category {
+        name: "Volume",
+        limit: Some(Limit { name: "zero volume", minimum: Ratio::ZERO }),
+        // base unit: cubic metre
+        units: &[
+            Unit::scaled("Cubic metre", "m³", Ratio::ONE),
+            Unit::scaled("Litre", "L", Ratio::new(1, 1000)),
+            Unit::scaled("Millilitre", "mL", Ratio::new(1, 1_000_000)),
+            Unit::scaled("US gallon", "gal", Ratio::new(3_785_411_784, 1_000_000_000_000)),
+            Unit::scaled("Imperial gallon", "imp gal", Ratio::new(454_609, 100_000_000)),
+            Unit::scaled("US fluid ounce", "fl oz", Ratio::new(3_785_411_784, 128_000_000_000_000)),
+        ],
+    },
+    Category {
+        name: "Speed",
+        limit: None,
+        // base unit: metre per second
+        units: &[
+            Unit::scaled("Metre per second", "m/s", Ratio::ONE),
+            Unit::scaled("Kilometre per hour", "km/h", Ratio::new(5, 18)),
+            Unit::scaled("Mile per hour", "mph", Ratio::new(1_609_344, 3_600_000)),
+            Unit::scaled("Knot", "kn", Ratio::new(1852, 3600)),
+            Unit::scaled("Foot per second", "ft/s", Ratio::new(3048, 10_000)),
+        ],
+    },
+    Category {
+        name: "Pressure",
+        limit: None,
+        // base unit: pascal
+        units: &[
+            Unit::scaled("Pascal", "Pa", Ratio::ONE),
+            Unit::scaled("Kilopascal", "kPa", Ratio::int(1000)),
+            Unit::scaled("Bar", "bar", Ratio::int(100_000)),
+            Unit::scaled("Atmosphere", "atm", Ratio::int(101_325)),
+            Unit::scaled("Pound per square inch", "psi", Ratio::new(44_482_216_152_605, 6_451_600_000)),
+            Unit::scaled("Torr", "Torr", Ratio::new(101_325, 760)),
+        ],
+    },
+    Category {
+        name: "Energy",
+        limit: None,
+        // base unit: joule
+        units: &[
+            Unit::scaled("Joule", "J", Ratio::ONE),
+            Unit::scaled("Kilojoule", "kJ", Ratio::int(1000)),
+            Unit::scaled("Calorie", "cal", Ratio::new(4184, 1000)),
+            Unit::scaled("Kilocalorie", "kcal", Ratio::int(4184)),
+            Unit::scaled("Watt hour", "Wh", Ratio::int(3600)),
+            Unit::scaled("Kilowatt hour", "kWh", Ratio::int(3_600_000)),
+            Unit::scaled("British thermal unit", "BTU", Ratio::new(105_505_585_262, 100_000_000)),
+            Unit::scaled("Electronvolt", "eV", Ratio::new(1_602_176_634, 10_000_000_000_000_000_000_000_000_000)),
+        ],
+    },
+];
+
+/// Looks up a unit by category and unit index
+pub fn unit(category: usize, unit: usize) -> &'static Unit {
+    &CATEGORIES[category].units[unit]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Looks up a unit by symbol, so the tests don't depend on the order of the registry
+    fn by_symbol(symbol: &str) -> &'static Unit {
+        CATEGORIES.iter().flat_map(|c| c.units.iter()).find(|u| u.symbol == symbol).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        let tolerance = 1e-12 * expected.abs().max(1.);
+        assert!((actual - expected).abs() <= tolerance, "{} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn temperatures_round_trip() {
+        let scales = ["℃", "℉", "K", "°R"].iter().map(|s| by_symbol(s)).collect::<Vec<_>>();
+        for from in scales.iter() {
+            for to in scales.iter() {
+                for &value in [-459.67, -40., 0., 21.5, 100., 1e6].iter() {
+                    assert_close(convert(convert(value, from, to), to, from), value);
+                }
+            }
+        }
+        assert_eq!(convert(100., by_symbol("℃"), by_symbol("℉")), 212.);
+        assert_eq!(convert(-40., by_symbol("℉"), by_symbol("℃")), -40.);
+        assert_eq!(convert(0., by_symbol("℃"), by_symbol("K")), 273.15);
+        assert_eq!(convert(0., by_symbol("℃"), by_symbol("°R")), 491.67);
+        assert_eq!(convert(0., by_symbol("K"), by_symbol("°R")), 0.);
+    }
+
+    #[test]
+    fn psi_is_a_pound_force_per_square_inch() {
+        assert_close(convert(1., by_symbol("psi"), by_symbol("Pa")), 6894.757293168361);
+        assert_close(convert(14.69594877551345, by_symbol("psi"), by_symbol("atm")), 1.);
+    }
+
+    #[test]
+    fn electronvolt_is_the_exact_si_value() {
+        assert_close(convert(1., by_symbol("eV"), by_symbol("J")), 1.602176634e-19);
+        assert_close(convert(1., by_symbol("kJ"), by_symbol("eV")), 6.241509074460763e21);
+    }
+
+    #[test]
+    fn btu_is_the_international_table_value() {
+        assert_close(convert(1., by_symbol("BTU"), by_symbol("J")), 1055.05585262);
+        assert_close(convert(1., by_symbol("kWh"), by_symbol("BTU")), 3412.141633127942);
+    }
+
+    #[test]
+    fn stone_is_fourteen_pounds() {
+        assert_eq!(convert(1., by_symbol("st"), by_symbol("lb")), 14.);
+        assert_close(convert(1., by_symbol("st"), by_symbol("kg")), 6.35029318);
+    }
+
+    #[test]
+    fn fluid_ounce_is_a_128th_of_a_gallon() {
+        assert_eq!(convert(128., by_symbol("fl oz"), by_symbol("gal")), 1.);
+        assert_close(convert(1., by_symbol("fl oz"), by_symbol("mL")), 29.5735295625);
+    }
+
+    #[test]
+    fn factors_too_large_to_combine_fall_back_to_floats() {
+        let tiny = Unit::scaled("tiny", "t", Ratio::new(1, 10i128.pow(20)));
+        let huge = Unit::scaled("huge", "h", Ratio::int(3i128.pow(40)));
+        assert_eq!(tiny.factor.div(huge.factor), None);
+
+        let expected = 1e-20 / 3f64.powi(40);
+        assert_close(convert(1., &tiny, &huge) / expected, 1.);
+        assert_close(convert(convert(5., &tiny, &huge), &huge, &tiny), 5.);
+    }
+}